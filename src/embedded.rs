@@ -0,0 +1,98 @@
+//! Bibliographies written inside the Markdown document itself.
+//!
+//! Two forms are recognised, either of which is merged with the external
+//! bibliographies (and wins over them on duplicate keys, as in Pandoc):
+//!
+//! ````markdown
+//! ---
+//! references:
+//! - id: cohen1963
+//!   type: article-journal
+//!   author: [{family: Cohen, given: Paul J.}]
+//!   issued: {date-parts: [[1963]]}
+//! ---
+//!
+//! ```bibtex
+//! @book{godel1940, author = {Gödel, Kurt}, title = {...}, year = {1940}}
+//! ```
+//! ````
+//!
+//! The `bibtex` blocks are removed from the output; the front matter is kept
+//! unless [`crate::ProcessingOptions::strip_front_matter`] is set.
+
+use crate::front_matter;
+use crate::import::{BibliographyFormat, BibliographySource};
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+static FENCE_PATTERN: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^ {0,3}(`{3,}|~{3,})[ \t]*([^\s`]*)").unwrap());
+
+/// Removes the embedded bibliographies from a document, returning the
+/// remaining Markdown and the bibliographies in document order.
+pub(crate) fn extract(markdown: &str) -> (String, Vec<BibliographySource>) {
+    let mut sources = Vec::new();
+    if let Some((_, yaml)) = front_matter::find(markdown) {
+        let yaml = &markdown[yaml];
+        let has_references = serde_yaml::from_str::<serde_yaml::Value>(yaml)
+            .is_ok_and(|value| value.get("references").is_some());
+        if has_references {
+            sources.push(BibliographySource {
+                name: "front matter".to_string(),
+                content: yaml.to_string(),
+                format: Some(BibliographyFormat::CslYaml),
+            });
+        }
+    }
+
+    let mut remaining = String::with_capacity(markdown.len());
+    // The fence of the open code block and whether it holds a bibliography.
+    let mut open_fence: Option<(String, bool)> = None;
+    let mut block = String::new();
+    for line in markdown.split_inclusive('\n') {
+        let content = line.trim_end_matches(['\n', '\r']);
+        let fence = FENCE_PATTERN.captures(content);
+
+        match (&open_fence, fence) {
+            (None, Some(cap)) => {
+                let is_bibliography =
+                    matches!(cap[2].to_lowercase().as_str(), "bibtex" | "biblatex" | "bib");
+                open_fence = Some((cap[1].to_string(), is_bibliography));
+                if is_bibliography {
+                    continue;
+                }
+            }
+            (Some((open, is_bibliography)), Some(cap))
+                if cap[1].starts_with(open.as_str()) && content.trim().len() == cap[1].len() =>
+            {
+                let is_bibliography = *is_bibliography;
+                open_fence = None;
+                if is_bibliography {
+                    sources.push(BibliographySource {
+                        name: format!("bibtex block {}", sources.len() + 1),
+                        content: std::mem::take(&mut block),
+                        format: Some(BibliographyFormat::BibTeX),
+                    });
+                    continue;
+                }
+            }
+            (Some((_, true)), _) => {
+                block.push_str(line);
+                continue;
+            }
+            _ => {}
+        }
+        remaining.push_str(line);
+    }
+
+    // An unterminated block still holds whatever entries it has so far.
+    if let Some((_, true)) = open_fence {
+        sources.push(BibliographySource {
+            name: format!("bibtex block {}", sources.len() + 1),
+            content: block,
+            format: Some(BibliographyFormat::BibTeX),
+        });
+    }
+
+    (remaining, sources)
+}
@@ -0,0 +1,497 @@
+//! Writes resolved entries as CSL-JSON, CSL-YAML, BibTeX, RIS or Hayagriva YAML.
+//!
+//! The mappings mirror the ones in [`crate::import`]: an article's
+//! `periodical` parent becomes its journal, a chapter's `book` parent its
+//! `booktitle`, and so on.
+
+use crate::import::BibliographyFormat;
+use hayagriva::types::{Date, EntryType, Person};
+use hayagriva::{Entry, Library};
+use serde_json::{json, Map, Value};
+
+/// Serialises entries, in the order given.
+pub(crate) fn write(entries: &[&Entry], format: BibliographyFormat) -> Result<String, String> {
+    match format {
+        BibliographyFormat::CslJson => {
+            let items: Vec<Value> = entries.iter().map(|entry| csl_item(entry)).collect();
+            serde_json::to_string_pretty(&items).map_err(|e| e.to_string())
+        }
+        // Under `references`, as in Pandoc's files and Markdown front matter.
+        BibliographyFormat::CslYaml => {
+            let items: Vec<Value> = entries.iter().map(|entry| csl_item(entry)).collect();
+            serde_yaml::to_string(&json!({ "references": items })).map_err(|e| e.to_string())
+        }
+        BibliographyFormat::BibTeX => Ok(entries
+            .iter()
+            .map(|entry| bibtex_entry(entry))
+            .collect::<Vec<_>>()
+            .join("\n\n")
+            + "\n"),
+        BibliographyFormat::Ris => Ok(entries.iter().map(|entry| ris_record(entry)).collect()),
+        BibliographyFormat::Yaml => {
+            let mut library = Library::new();
+            for entry in entries {
+                library.push(entry);
+            }
+            hayagriva::io::to_yaml_str(&library).map_err(|e| e.to_string())
+        }
+        _ => Err(format!("cannot export {:?} bibliographies", format)),
+    }
+}
+
+/// The fields shared by all three formats, with container fields taken from
+/// the first parent when the entry itself lacks them.
+struct Fields<'a> {
+    entry: &'a Entry,
+    parent: Option<&'a Entry>,
+}
+
+impl<'a> Fields<'a> {
+    fn new(entry: &'a Entry) -> Self {
+        Fields { entry, parent: entry.parents().first() }
+    }
+
+    fn parent_type(&self) -> Option<&EntryType> {
+        self.parent.map(Entry::entry_type)
+    }
+
+    fn title(&self) -> Option<String> {
+        self.entry.title().map(|title| title.to_string())
+    }
+
+    fn container_title(&self) -> Option<String> {
+        self.parent?.title().map(|title| title.to_string())
+    }
+
+    /// A field of the entry, or of its parent.
+    fn inherited<T: ToString>(&self, get: impl Fn(&'a Entry) -> Option<T>) -> Option<String> {
+        get(self.entry)
+            .or_else(|| self.parent.and_then(&get))
+            .map(|value| value.to_string())
+    }
+
+    fn date(&self) -> Option<&'a Date> {
+        self.entry.date().or_else(|| self.parent?.date())
+    }
+
+    fn editors(&self) -> Option<&'a [Person]> {
+        self.entry.editors().or_else(|| self.parent?.editors())
+    }
+
+    fn publisher(&self) -> Option<String> {
+        self.inherited(|entry| entry.publisher()?.name().cloned())
+    }
+
+    fn publisher_place(&self) -> Option<String> {
+        self.inherited(|entry| entry.publisher()?.location().cloned())
+            .or_else(|| self.inherited(|entry| entry.location().cloned()))
+    }
+
+    fn pages(&self) -> Option<String> {
+        self.inherited(|entry| entry.page_range().cloned())
+    }
+
+    fn volume(&self) -> Option<String> {
+        self.inherited(|entry| entry.volume().cloned())
+    }
+
+    fn issue(&self) -> Option<String> {
+        self.inherited(|entry| entry.issue().cloned())
+    }
+
+    fn doi(&self) -> Option<&'a str> {
+        self.entry.doi().or_else(|| self.parent?.doi())
+    }
+
+    fn isbn(&self) -> Option<&'a str> {
+        self.entry.isbn().or_else(|| self.parent?.isbn())
+    }
+
+    fn issn(&self) -> Option<&'a str> {
+        self.entry.issn().or_else(|| self.parent?.issn())
+    }
+}
+
+/// The family name with its prefix, such as `van Gogh`.
+fn family_name(person: &Person) -> String {
+    match &person.prefix {
+        Some(prefix) => format!("{} {}", prefix, person.name),
+        None => person.name.clone(),
+    }
+}
+
+/// `Family, Given` as written in BibTeX, or `Family, Suffix, Given`.
+fn bibtex_name(person: &Person) -> String {
+    let family = family_name(person);
+    match (&person.given_name, &person.suffix) {
+        (Some(given), Some(suffix)) => format!("{}, {}, {}", family, suffix, given),
+        (Some(given), None) => format!("{}, {}", family, given),
+        (None, _) => family,
+    }
+}
+
+/// `Family, Given, Suffix`, the order of RIS names.
+fn ris_name(person: &Person) -> String {
+    let family = family_name(person);
+    match (&person.given_name, &person.suffix) {
+        (Some(given), Some(suffix)) => format!("{}, {}, {}", family, given, suffix),
+        (Some(given), None) => format!("{}, {}", family, given),
+        (None, _) => family,
+    }
+}
+
+/// `YYYY`, `YYYY-MM` or `YYYY-MM-DD`.
+fn iso_date(date: &Date) -> String {
+    match (date.month, date.day) {
+        (Some(month), Some(day)) => format!("{:04}-{:02}-{:02}", date.year, month + 1, day + 1),
+        (Some(month), None) => format!("{:04}-{:02}", date.year, month + 1),
+        _ => format!("{:04}", date.year),
+    }
+}
+
+/// CSL type of an entry, the reverse of `csl_type_to_hayagriva`.
+fn csl_type(fields: &Fields) -> &'static str {
+    match (fields.entry.entry_type(), fields.parent_type()) {
+        (EntryType::Article, Some(EntryType::Newspaper)) => "article-newspaper",
+        (EntryType::Article, Some(EntryType::Proceedings)) => "paper-conference",
+        (EntryType::Article, Some(EntryType::Blog)) => "post-weblog",
+        (EntryType::Article, _) => "article-journal",
+        (EntryType::Chapter | EntryType::Anthos, _) => "chapter",
+        (EntryType::Entry, _) => "entry-encyclopedia",
+        (EntryType::Book | EntryType::Proceedings | EntryType::Reference, _) => "book",
+        (EntryType::Anthology, _) => "collection",
+        (EntryType::Periodical | EntryType::Newspaper, _) => "periodical",
+        (EntryType::Thesis, _) => "thesis",
+        (EntryType::Report, _) => "report",
+        (EntryType::Web, _) => "webpage",
+        (EntryType::Post | EntryType::Thread, _) => "post",
+        (EntryType::Manuscript, _) => "manuscript",
+        (EntryType::Patent, _) => "patent",
+        (EntryType::Case, _) => "legal_case",
+        (EntryType::Legislation, _) => "legislation",
+        (EntryType::Video, _) => "motion_picture",
+        (EntryType::Audio, _) => "song",
+        (EntryType::Artwork, _) => "graphic",
+        (EntryType::Performance, _) => "performance",
+        (EntryType::Conference, _) => "event",
+        (EntryType::Repository, _) => "software",
+        _ => "document",
+    }
+}
+
+fn csl_names(persons: &[Person]) -> Value {
+    Value::Array(
+        persons
+            .iter()
+            .map(|person| {
+                let mut name = Map::new();
+                match (&person.given_name, &person.prefix) {
+                    (Some(given), _) => {
+                        name.insert("family".into(), json!(person.name));
+                        name.insert("given".into(), json!(given));
+                    }
+                    // A particle needs a family name to attach to.
+                    (None, Some(_)) => {
+                        name.insert("family".into(), json!(person.name));
+                    }
+                    (None, None) => {
+                        name.insert("literal".into(), json!(person.name));
+                    }
+                }
+                if let Some(prefix) = &person.prefix {
+                    name.insert("non-dropping-particle".into(), json!(prefix));
+                }
+                if let Some(suffix) = &person.suffix {
+                    name.insert("suffix".into(), json!(suffix));
+                }
+                Value::Object(name)
+            })
+            .collect(),
+    )
+}
+
+fn csl_date(date: &Date) -> Value {
+    let mut parts = vec![date.year as i64];
+    if let Some(month) = date.month {
+        parts.push(month as i64 + 1);
+        if let Some(day) = date.day {
+            parts.push(day as i64 + 1);
+        }
+    }
+    json!({ "date-parts": [parts] })
+}
+
+/// Builds a CSL item from an entry.
+pub(crate) fn csl_item(entry: &Entry) -> Value {
+    let fields = Fields::new(entry);
+    let mut item = Map::new();
+    item.insert("id".into(), json!(entry.key()));
+    item.insert("type".into(), json!(csl_type(&fields)));
+
+    let mut text = |name: &str, value: Option<String>| {
+        if let Some(value) = value {
+            item.insert(name.to_string(), json!(value));
+        }
+    };
+    text("title", fields.title());
+    text("container-title", fields.container_title());
+    text("volume", fields.volume());
+    text("issue", fields.issue());
+    text("page", fields.pages());
+    text("publisher", fields.publisher());
+    text("publisher-place", fields.publisher_place());
+    text("edition", entry.edition().map(|edition| edition.to_string()));
+    text("genre", entry.genre().map(|genre| genre.to_string()));
+    text("abstract", entry.abstract_().map(|text| text.to_string()));
+    text("note", entry.note().map(|note| note.to_string()));
+    text("language", entry.language().map(|language| language.to_string()));
+    text("DOI", fields.doi().map(str::to_string));
+    text("ISBN", fields.isbn().map(str::to_string));
+    text("ISSN", fields.issn().map(str::to_string));
+    text("URL", entry.url().map(|url| url.value.to_string()));
+
+    if let Some(authors) = entry.authors() {
+        item.insert("author".into(), csl_names(authors));
+    }
+    if let Some(editors) = fields.editors() {
+        item.insert("editor".into(), csl_names(editors));
+    }
+    if let Some(date) = fields.date() {
+        item.insert("issued".into(), csl_date(date));
+    }
+    if let Some(visited) = entry.url().and_then(|url| url.visit_date.as_ref()) {
+        item.insert("accessed".into(), csl_date(visited));
+    }
+    Value::Object(item)
+}
+
+/// BibLaTeX entry type and the field holding the container title.
+fn bibtex_type(fields: &Fields) -> (&'static str, &'static str) {
+    match (fields.entry.entry_type(), fields.parent_type()) {
+        (EntryType::Article, Some(EntryType::Proceedings)) => ("inproceedings", "booktitle"),
+        (EntryType::Article, _) => ("article", "journal"),
+        (EntryType::Chapter | EntryType::Anthos, _) => ("incollection", "booktitle"),
+        (EntryType::Entry, _) => ("inreference", "booktitle"),
+        (EntryType::Book | EntryType::Reference, _) => ("book", "series"),
+        (EntryType::Anthology, _) => ("collection", "series"),
+        (EntryType::Proceedings, _) => ("proceedings", "series"),
+        (EntryType::Periodical | EntryType::Newspaper, _) => ("periodical", "series"),
+        (EntryType::Thesis, _) => ("thesis", "series"),
+        (EntryType::Report, _) => ("report", "series"),
+        (EntryType::Web | EntryType::Blog | EntryType::Post, _) => ("online", "organization"),
+        (EntryType::Manuscript, _) => ("unpublished", "howpublished"),
+        (EntryType::Patent, _) => ("patent", "howpublished"),
+        (EntryType::Repository, _) => ("software", "howpublished"),
+        _ => ("misc", "howpublished"),
+    }
+}
+
+/// Writes page ranges with BibTeX's `--`.
+fn bibtex_pages(pages: &str) -> String {
+    if pages.contains("--") {
+        pages.to_string()
+    } else {
+        pages.replace(['-', '–'], "--")
+    }
+}
+
+fn bibtex_names(persons: &[Person]) -> String {
+    persons
+        .iter()
+        .map(|person| match (&person.given_name, &person.prefix) {
+            (Some(_), _) => escape_bibtex(&bibtex_name(person)),
+            // Keeps organisations from being split into first and last names;
+            // a lowercase prefix outside the braces is still read as one.
+            (None, Some(prefix)) => {
+                format!("{} {{{}}}", escape_bibtex(prefix), escape_bibtex(&person.name))
+            }
+            (None, None) => format!("{{{}}}", escape_bibtex(&person.name)),
+        })
+        .collect::<Vec<_>>()
+        .join(" and ")
+}
+
+/// Escapes LaTeX's special characters in a plain-text value. Braces become
+/// `\{`/`\}` when they pair up, since BibTeX counts them even when escaped,
+/// and `\textbraceleft{}`/`\textbraceright{}` otherwise.
+fn escape_bibtex(value: &str) -> String {
+    let mut depth: usize = 0;
+    let balanced = value.chars().all(|c| match c {
+        '{' => {
+            depth += 1;
+            true
+        }
+        '}' => depth.checked_sub(1).map(|d| depth = d).is_some(),
+        _ => true,
+    }) && depth == 0;
+
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '{' if balanced => escaped.push_str("\\{"),
+            '}' if balanced => escaped.push_str("\\}"),
+            '{' => escaped.push_str("\\textbraceleft{}"),
+            '}' => escaped.push_str("\\textbraceright{}"),
+            '\\' => escaped.push_str("\\textbackslash{}"),
+            '%' | '&' | '$' | '#' | '_' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '~' => escaped.push_str("\\textasciitilde{}"),
+            '^' => escaped.push_str("\\textasciicircum{}"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// A value between braces, escaped.
+fn braced(value: impl AsRef<str>) -> String {
+    format!("{{{}}}", escape_bibtex(value.as_ref()))
+}
+
+/// BibTeX's month macros, which styles expand to the month's name.
+const BIBTEX_MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+/// Builds a BibTeX entry from an entry, with biblatex types and fields where
+/// BibTeX has none.
+pub(crate) fn bibtex_entry(entry: &Entry) -> String {
+    let fields = Fields::new(entry);
+    let (entry_type, container_field) = bibtex_type(&fields);
+    // Values are written as they will appear between `=` and `,`.
+    let mut lines: Vec<(&str, String)> = Vec::new();
+
+    if let Some(authors) = entry.authors() {
+        lines.push(("author", format!("{{{}}}", bibtex_names(authors))));
+    }
+    if let Some(editors) = fields.editors() {
+        lines.push(("editor", format!("{{{}}}", bibtex_names(editors))));
+    }
+    let mut push = |name: &'static str, value: Option<String>| {
+        if let Some(value) = value {
+            lines.push((name, value));
+        }
+    };
+    push("title", fields.title().map(braced));
+    push(container_field, fields.container_title().map(braced));
+    // Classic styles only read `year` and `month`; a day needs biblatex's `date`.
+    let date = fields.date();
+    push("year", date.map(|date| braced(date.year.to_string())));
+    push(
+        "month",
+        date.and_then(|date| BIBTEX_MONTHS.get(date.month? as usize))
+            .map(|month| month.to_string()),
+    );
+    push("date", date.filter(|date| date.day.is_some()).map(|date| braced(iso_date(date))));
+    push("volume", fields.volume().map(braced));
+    push("number", fields.issue().map(braced));
+    push("pages", fields.pages().map(|pages| braced(bibtex_pages(&pages))));
+    push("publisher", fields.publisher().map(braced));
+    push("location", fields.publisher_place().map(braced));
+    push("edition", entry.edition().map(|edition| braced(edition.to_string())));
+    push("type", entry.genre().map(|genre| braced(genre.to_string())));
+    // `doi` and `url` are read verbatim, so they are only braced.
+    push("doi", fields.doi().map(|doi| format!("{{{}}}", doi)));
+    push("isbn", fields.isbn().map(braced));
+    push("issn", fields.issn().map(braced));
+    push("url", entry.url().map(|url| format!("{{{}}}", url.value)));
+    push(
+        "urldate",
+        entry.url().and_then(|url| url.visit_date.as_ref()).map(|date| braced(iso_date(date))),
+    );
+    push("language", entry.language().map(|language| braced(language.to_string())));
+    push("note", entry.note().map(|note| braced(note.to_string())));
+    push("abstract", entry.abstract_().map(|text| braced(text.to_string())));
+
+    let body: Vec<String> = lines
+        .iter()
+        .map(|(name, value)| format!("  {} = {},", name, value))
+        .collect();
+    format!("@{}{{{},\n{}\n}}", entry_type, entry.key(), body.join("\n"))
+}
+
+/// RIS reference type of an entry.
+fn ris_type(fields: &Fields) -> &'static str {
+    match (fields.entry.entry_type(), fields.parent_type()) {
+        (EntryType::Article, Some(EntryType::Newspaper)) => "NEWS",
+        (EntryType::Article, Some(EntryType::Proceedings)) => "CPAPER",
+        (EntryType::Article, Some(EntryType::Blog)) => "BLOG",
+        (EntryType::Article, _) => "JOUR",
+        (EntryType::Chapter | EntryType::Anthos, _) => "CHAP",
+        (EntryType::Entry, _) => "ENCYC",
+        (EntryType::Book | EntryType::Reference, _) => "BOOK",
+        (EntryType::Anthology, _) => "EDBOOK",
+        (EntryType::Proceedings, _) => "CONF",
+        (EntryType::Thesis, _) => "THES",
+        (EntryType::Report, _) => "RPRT",
+        (EntryType::Web | EntryType::Post, _) => "ELEC",
+        (EntryType::Manuscript, _) => "UNPB",
+        (EntryType::Patent, _) => "PAT",
+        (EntryType::Case, _) => "CASE",
+        (EntryType::Legislation, _) => "STAT",
+        (EntryType::Video, _) => "VIDEO",
+        (EntryType::Audio, _) => "SOUND",
+        (EntryType::Artwork, _) => "ART",
+        (EntryType::Repository, _) => "COMP",
+        _ => "GEN",
+    }
+}
+
+/// Builds a RIS record from an entry.
+pub(crate) fn ris_record(entry: &Entry) -> String {
+    let fields = Fields::new(entry);
+    let is_book = matches!(ris_type(&fields), "BOOK" | "EDBOOK");
+    let mut tags: Vec<(&str, String)> = vec![("TY", ris_type(&fields).to_string())];
+    tags.push(("ID", entry.key().to_string()));
+
+    for person in entry.authors().unwrap_or_default() {
+        tags.push(("AU", ris_name(person)));
+    }
+    for person in fields.editors().unwrap_or_default() {
+        tags.push(("ED", ris_name(person)));
+    }
+    let mut push = |tag: &'static str, value: Option<String>| {
+        if let Some(value) = value {
+            tags.push((tag, value));
+        }
+    };
+    push("TI", fields.title());
+    push("T2", fields.container_title());
+    push("PY", fields.date().map(|date| date.year.to_string()));
+    push("DA", fields.date().map(|date| iso_date(date).replace('-', "/")));
+    push("VL", fields.volume());
+    push("IS", fields.issue());
+    if let Some(pages) = fields.pages() {
+        let mut range = pages.splitn(2, ['-', '–']);
+        push("SP", range.next().map(|page| page.trim().to_string()));
+        push(
+            "EP",
+            range.next().map(|page| page.trim_start_matches('-').trim().to_string()),
+        );
+    }
+    push("PB", fields.publisher());
+    push("CY", fields.publisher_place());
+    push("ET", entry.edition().map(|edition| edition.to_string()));
+    push("M3", entry.genre().map(|genre| genre.to_string()));
+    push("SN", if is_book { fields.isbn() } else { fields.issn() }.map(str::to_string));
+    push("DO", fields.doi().map(str::to_string));
+    push("UR", entry.url().map(|url| url.value.to_string()));
+    push("LA", entry.language().map(|language| language.to_string()));
+    push("AB", entry.abstract_().map(|text| text.to_string()));
+    push("N1", entry.note().map(|note| note.to_string()));
+
+    // A tag's value ends at the line break, so abstracts and notes spanning
+    // several lines are joined into one.
+    let mut record: String = tags
+        .iter()
+        .map(|(tag, value)| {
+            let value: Vec<&str> =
+                value.lines().map(str::trim).filter(|line| !line.is_empty()).collect();
+            format!("{}  - {}\n", tag, value.join(" "))
+        })
+        .collect();
+    record.push_str("ER  - \n\n");
+    record
+}
@@ -0,0 +1,110 @@
+//! YAML front matter, and the Pandoc metadata fields read from it:
+//!
+//! ```yaml
+//! ---
+//! bibliography: [lab.bib, paper.bib]
+//! csl: chicago-author-date.csl
+//! lang: de-DE
+//! link-citations: true
+//! reference-section-title: Quellen
+//! nocite: "@Cohen63, @Godel40"
+//! ---
+//! ```
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Deserialize;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+static FRONT_MATTER_PATTERN: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"\A\u{feff}?---[ \t]*\r?\n((?s:.*?)\r?\n)?(?:---|\.\.\.)[ \t]*(?:\r?\n|\z)").unwrap()
+});
+
+/// The YAML front matter at the very start of a document: the range of the
+/// whole block, delimiters included, and of the YAML inside it.
+///
+/// As in Pandoc, the block is only metadata when its YAML is a mapping (or
+/// empty); `---`, a paragraph and `---` are a thematic break and a setext
+/// heading, and stay in the body. Invalid YAML is left for
+/// [`FrontMatter::parse`] to report.
+pub(crate) fn find(markdown: &str) -> Option<(Range<usize>, Range<usize>)> {
+    let cap = FRONT_MATTER_PATTERN.captures(markdown)?;
+    let block = cap.get(0)?.range();
+    let yaml = cap.get(1).map_or(block.end..block.end, |m| m.range());
+    match serde_yaml::from_str::<serde_yaml::Value>(&markdown[yaml.clone()]) {
+        Ok(serde_yaml::Value::Mapping(_) | serde_yaml::Value::Null) | Err(_) => Some((block, yaml)),
+        Ok(_) => None,
+    }
+}
+
+/// Settings read from a document's front matter. Fields not given are empty;
+/// other metadata, such as `title`, is ignored.
+///
+/// The processing functions apply `lang`, `link-citations`,
+/// `reference-section-title` and `nocite` themselves (see
+/// [`crate::ProcessingOptions::with_front_matter`]); `bibliography` and `csl`
+/// name files, which the caller reads.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct FrontMatter {
+    /// Bibliography files, relative to the document.
+    #[serde(deserialize_with = "one_or_many")]
+    pub bibliography: Vec<String>,
+    /// CSL style file, relative to the document.
+    pub csl: Option<String>,
+    /// Locale code, such as `de-DE`.
+    pub lang: Option<String>,
+    pub link_citations: Option<bool>,
+    pub reference_section_title: Option<String>,
+    /// Keys to list without citing them, such as `@Cohen63` or `@*`.
+    #[serde(deserialize_with = "one_or_many")]
+    pub nocite: Vec<String>,
+}
+
+impl FrontMatter {
+    /// Reads the front matter of a document; a document without one gives
+    /// the default.
+    pub fn parse(markdown: &str) -> Result<Self, String> {
+        match find(markdown) {
+            Some((_, yaml)) if !markdown[yaml.clone()].trim().is_empty() => {
+                serde_yaml::from_str(&markdown[yaml])
+                    .map_err(|e| format!("Front matter parsing error: {}", e))
+            }
+            _ => Ok(Self::default()),
+        }
+    }
+
+    /// The bibliography files, resolved against the directory of the
+    /// document at `markdown_path`.
+    pub fn bibliography_paths(&self, markdown_path: &Path) -> Vec<PathBuf> {
+        self.bibliography.iter().map(|path| resolve(markdown_path, path)).collect()
+    }
+
+    /// The CSL style file, resolved against the directory of the document at
+    /// `markdown_path`.
+    pub fn csl_path(&self, markdown_path: &Path) -> Option<PathBuf> {
+        self.csl.as_deref().map(|path| resolve(markdown_path, path))
+    }
+}
+
+/// A path relative to the document's directory; absolute paths are kept.
+fn resolve(markdown_path: &Path, path: &str) -> PathBuf {
+    markdown_path.parent().unwrap_or(Path::new("")).join(path)
+}
+
+/// Accepts a single string as well as a list.
+fn one_or_many<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match Option::<OneOrMany>::deserialize(deserializer)? {
+        Some(OneOrMany::One(value)) => vec![value],
+        Some(OneOrMany::Many(values)) => values,
+        None => Vec::new(),
+    })
+}
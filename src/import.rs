@@ -0,0 +1,932 @@
+//! Reads bibliographies into hayagriva entries.
+//!
+//! BibTeX and Hayagriva YAML go through hayagriva's own loaders. CSL-JSON, as
+//! exported by Zotero and most reference managers, is mapped onto hayagriva's
+//! data model (an article's journal becomes its `periodical` parent, and so
+//! on) so that it is grouped, resolved and rendered exactly like BibTeX. RIS
+//! and EndNote XML records are first translated to CSL items; whatever has no
+//! CSL equivalent is listed in [`LoadedBibliography::unmapped`].
+
+mod endnote;
+mod ris;
+
+use crate::latex;
+use hayagriva::io::{from_biblatex, from_yaml_str};
+use hayagriva::types::{ChunkKind, FormatString, Person, StringChunk};
+use hayagriva::{Entry, Library};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde_json::{json, Map, Value};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+static BIBTEX_ENTRY_PATTERN: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?m)^\s*@\w+\s*[{(]").unwrap());
+// A top-level mapping key such as `harry:` at the start of a line.
+static YAML_KEY_PATTERN: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?m)^[^\s#%@<-][^:\n]*:\s*$").unwrap());
+static CSL_YAML_PATTERN: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?m)^(?:references:\s*$|-\s+id:)").unwrap());
+static RIS_TYPE_PATTERN: Lazy<Regex> = Lazy::new(|| Regex::new(r"^TY\s{1,2}-").unwrap());
+
+/// The formats a bibliography can be read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BibliographyFormat {
+    BibTeX,
+    CslJson,
+    /// Hayagriva's YAML format.
+    Yaml,
+    /// CSL items in YAML, as a list or under a `references` key (Pandoc's
+    /// format, also used in Markdown front matter).
+    CslYaml,
+    Ris,
+    /// The XML export of EndNote.
+    EndNoteXml,
+}
+
+impl BibliographyFormat {
+    /// Guesses the format from a file extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "bib" | "bibtex" | "biblatex" => Some(Self::BibTeX),
+            "json" | "csljson" => Some(Self::CslJson),
+            "yml" | "yaml" => Some(Self::Yaml),
+            "ris" => Some(Self::Ris),
+            "xml" => Some(Self::EndNoteXml),
+            _ => None,
+        }
+    }
+
+    /// Guesses the format from the content, defaulting to BibTeX.
+    pub fn detect(input: &str) -> Self {
+        let trimmed = input.trim_start_matches('\u{feff}').trim_start();
+        if trimmed.starts_with('[') || trimmed.starts_with('{') {
+            Self::CslJson
+        } else if RIS_TYPE_PATTERN.is_match(trimmed) {
+            Self::Ris
+        } else if trimmed.starts_with('<') {
+            Self::EndNoteXml
+        } else if BIBTEX_ENTRY_PATTERN.is_match(input) {
+            Self::BibTeX
+        } else if CSL_YAML_PATTERN.is_match(input) {
+            Self::CslYaml
+        } else if YAML_KEY_PATTERN.is_match(input) {
+            Self::Yaml
+        } else {
+            Self::BibTeX
+        }
+    }
+}
+
+impl FromStr for BibliographyFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "bibtex" | "biblatex" | "bib" => Ok(Self::BibTeX),
+            "csl-json" | "csljson" | "json" => Ok(Self::CslJson),
+            "yaml" | "yml" | "hayagriva" => Ok(Self::Yaml),
+            "csl-yaml" | "cslyaml" => Ok(Self::CslYaml),
+            "ris" => Ok(Self::Ris),
+            "endnote-xml" | "endnote" | "xml" => Ok(Self::EndNoteXml),
+            _ => Err(format!("unknown bibliography format `{}`", s)),
+        }
+    }
+}
+
+/// A bibliography file to merge with others.
+#[derive(Debug, Clone)]
+pub struct BibliographySource {
+    /// Name used in conflict reports, usually the file path.
+    pub name: String,
+    pub content: String,
+    /// Detected from the content when `None`.
+    pub format: Option<BibliographyFormat>,
+}
+
+/// Which entry is kept when several bibliographies define the same key.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicatePrecedence {
+    /// The bibliography given first wins.
+    #[default]
+    First,
+    /// The bibliography given last wins, e.g. a per-paper file listed after a
+    /// shared one.
+    Last,
+    /// Duplicate keys are an error.
+    Error,
+}
+
+impl FromStr for DuplicatePrecedence {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "first" => Ok(Self::First),
+            "last" => Ok(Self::Last),
+            "error" => Ok(Self::Error),
+            _ => Err(format!("unknown precedence `{}`, expected first, last or error", s)),
+        }
+    }
+}
+
+/// A problem found while merging bibliographies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeConflict {
+    /// Two bibliographies define the same key; only the entry from `kept` is used.
+    DuplicateKey { key: String, kept: String, dropped: String },
+    /// Two entries with different keys that seem to describe the same work,
+    /// because they share a DOI or their title and year. Both are kept.
+    LikelyDuplicate { keys: (String, String), reason: String },
+}
+
+impl fmt::Display for MergeConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MergeConflict::DuplicateKey { key, kept, dropped } => {
+                write!(f, "duplicate key `{}`: using {}, ignoring {}", key, kept, dropped)
+            }
+            MergeConflict::LikelyDuplicate { keys, reason } => {
+                write!(f, "`{}` and `{}` look like the same work ({})", keys.0, keys.1, reason)
+            }
+        }
+    }
+}
+
+/// The type and fields of an entry as written in its source file, which
+/// bibliography filters match against.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SourceRecord {
+    pub entry_type: String,
+    /// Field values by lowercase field name.
+    pub fields: HashMap<String, String>,
+    /// The entry exactly as written, for BibTeX sources.
+    pub bibtex: Option<String>,
+    /// The CSL item, as written for CSL-JSON and CSL-YAML sources and as
+    /// translated for RIS and EndNote XML.
+    pub csl: Option<Value>,
+}
+
+/// A field of an imported record that could not be translated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnmappedField {
+    /// Key of the entry the field belongs to.
+    pub key: String,
+    /// Field name as written in the source, e.g. the RIS tag `M1` or the
+    /// EndNote element `custom1`.
+    pub field: String,
+    pub value: String,
+}
+
+/// A parsed bibliography and the source record of each of its entries.
+#[derive(Debug, Default)]
+pub(crate) struct LoadedBibliography {
+    pub library: Library,
+    pub records: HashMap<String, SourceRecord>,
+    /// Fields dropped while translating RIS or EndNote XML.
+    pub unmapped: Vec<UnmappedField>,
+    /// `@string`, `@preamble` and `@xdata` blocks of BibTeX sources, as
+    /// written.
+    pub bibtex_macros: Vec<String>,
+}
+
+/// Parses a bibliography, detecting the format from the content if not given.
+pub(crate) fn load(
+    input: &str,
+    format: Option<BibliographyFormat>,
+) -> Result<LoadedBibliography, String> {
+    let mut loaded = match format.unwrap_or_else(|| BibliographyFormat::detect(input)) {
+        BibliographyFormat::BibTeX => load_bibtex(input)?,
+        BibliographyFormat::CslJson => load_csl_json(input)?,
+        // Pandoc's CSL-YAML files share the extension.
+        BibliographyFormat::Yaml if CSL_YAML_PATTERN.is_match(input) => load_csl_yaml(input)?,
+        BibliographyFormat::Yaml => load_yaml(input)?,
+        BibliographyFormat::CslYaml => load_csl_yaml(input)?,
+        BibliographyFormat::Ris => {
+            let (items, unmapped) = ris::parse(input);
+            LoadedBibliography { unmapped, ..load_csl_items(&items)? }
+        }
+        BibliographyFormat::EndNoteXml => {
+            let (items, unmapped) = endnote::parse(input)?;
+            LoadedBibliography { unmapped, ..load_csl_items(&items)? }
+        }
+    };
+    loaded.library = decode_latex(&loaded.library);
+    Ok(loaded)
+}
+
+/// Decodes the LaTeX left in names and titles, of the entries and their
+/// parents, so that they are grouped and rendered as Unicode.
+fn decode_latex(library: &Library) -> Library {
+    fn decode_entry(entry: &mut Entry) {
+        if let Some(title) = entry.title() {
+            entry.set_title(decode_format_string(title));
+        }
+        if let Some(authors) = entry.authors() {
+            entry.set_authors(authors.iter().map(decode_person).collect());
+        }
+        if let Some(editors) = entry.editors() {
+            entry.set_editors(editors.iter().map(decode_person).collect());
+        }
+        let mut parents = entry.parents().to_vec();
+        parents.iter_mut().for_each(decode_entry);
+        entry.set_parents(parents);
+    }
+
+    let mut decoded = Library::new();
+    for entry in library.iter() {
+        let mut entry = entry.clone();
+        decode_entry(&mut entry);
+        decoded.push(&entry);
+    }
+    decoded
+}
+
+fn decode_format_string(string: &FormatString) -> FormatString {
+    let mut string = string.clone();
+    decode_chunks(&mut string.value.0);
+    if let Some(short) = string.short.as_mut() {
+        decode_chunks(&mut short.0);
+    }
+    string
+}
+
+fn decode_chunks(chunks: &mut [StringChunk]) {
+    // An accent split from its braced argument is carried over to it.
+    let mut pending = String::new();
+    for chunk in chunks.iter_mut().filter(|chunk| chunk.kind != ChunkKind::Math) {
+        let value = std::mem::take(&mut pending) + &chunk.value;
+        let (text, accent) = latex::split_trailing_accent(&value);
+        chunk.value = latex::to_unicode(text);
+        pending = accent.to_string();
+    }
+}
+
+fn decode_person(person: &Person) -> Person {
+    let decode = |part: &Option<String>| part.as_deref().map(latex::to_unicode);
+    Person {
+        name: latex::to_unicode(&person.name),
+        given_name: decode(&person.given_name),
+        prefix: decode(&person.prefix),
+        suffix: decode(&person.suffix),
+        alias: person.alias.clone(),
+    }
+}
+
+/// Merges parsed bibliographies, given with their names, in order.
+///
+/// Entries with the same key are resolved by `precedence`, except that the
+/// first `embedded` bibliographies always win over the rest; entries that
+/// look like the same work under different keys are only reported.
+pub(crate) fn merge(
+    bibliographies: Vec<(String, LoadedBibliography)>,
+    embedded: usize,
+    precedence: DuplicatePrecedence,
+) -> Result<(LoadedBibliography, Vec<MergeConflict>), String> {
+    let mut conflicts = Vec::new();
+    let mut order: Vec<String> = Vec::new();
+    // Sources are told apart by index: names, such as `refs.bib` in two
+    // directories, need not be unique.
+    let mut chosen: HashMap<String, (hayagriva::Entry, usize)> = HashMap::new();
+    let mut merged = LoadedBibliography::default();
+
+    for (index, (name, loaded)) in bibliographies.iter().enumerate() {
+        for entry in loaded.library.iter() {
+            let key = entry.key().to_string();
+            let Some(&(_, previous)) = chosen.get(&key) else {
+                order.push(key.clone());
+                chosen.insert(key.clone(), (entry.clone(), index));
+                continue;
+            };
+            // The first `embedded` bibliographies, written in the document,
+            // win over the others whatever the precedence, as in Pandoc.
+            let overridden = previous < embedded && index >= embedded;
+            let previous = &bibliographies[previous].0;
+            if overridden {
+                conflicts.push(MergeConflict::DuplicateKey { key, kept: previous.clone(), dropped: name.clone() });
+                continue;
+            }
+            let (kept, dropped) = match precedence {
+                DuplicatePrecedence::First => (previous.to_string(), name.clone()),
+                DuplicatePrecedence::Last => (name.clone(), previous.to_string()),
+                DuplicatePrecedence::Error => {
+                    return Err(format!("duplicate key `{}` in {} and {}", key, previous, name))
+                }
+            };
+            if precedence == DuplicatePrecedence::Last {
+                chosen.insert(key.clone(), (entry.clone(), index));
+            }
+            conflicts.push(MergeConflict::DuplicateKey { key, kept, dropped });
+        }
+    }
+
+    for key in &order {
+        let (entry, source) = &chosen[key];
+        merged.library.push(entry);
+        if let Some(record) = bibliographies[*source].1.records.get(key) {
+            merged.records.insert(key.clone(), record.clone());
+        }
+    }
+    for (_, loaded) in bibliographies {
+        merged.unmapped.extend(loaded.unmapped);
+        merged.bibtex_macros.extend(loaded.bibtex_macros);
+    }
+
+    conflicts.extend(likely_duplicates(&merged.library));
+    Ok((merged, conflicts))
+}
+
+/// Pairs of entries sharing a DOI, or a normalised title and a year.
+fn likely_duplicates(library: &Library) -> Vec<MergeConflict> {
+    let mut conflicts = Vec::new();
+    let mut by_doi: HashMap<String, String> = HashMap::new();
+    let mut by_title: HashMap<(String, i32), String> = HashMap::new();
+
+    for entry in library.iter() {
+        let key = entry.key().to_string();
+        if let Some(doi) = entry.doi().map(normalize_doi).filter(|doi| !doi.is_empty()) {
+            if let Some(other) = by_doi.get(&doi) {
+                conflicts.push(MergeConflict::LikelyDuplicate {
+                    keys: (other.clone(), key),
+                    reason: format!("same DOI {}", doi),
+                });
+                continue;
+            }
+            by_doi.insert(doi, key.clone());
+        }
+
+        let title: Option<String> = entry.title().map(|title| {
+            latex::ascii_fold(&title.to_string())
+                .chars()
+                .filter(|c| c.is_alphanumeric())
+                .flat_map(char::to_lowercase)
+                .collect()
+        });
+        let year = entry.date().map(|date| date.year);
+        if let (Some(title), Some(year)) = (title.filter(|t| !t.is_empty()), year) {
+            if let Some(other) = by_title.get(&(title.clone(), year)) {
+                conflicts.push(MergeConflict::LikelyDuplicate {
+                    keys: (other.clone(), key),
+                    reason: format!("same title and year {}", year),
+                });
+                continue;
+            }
+            by_title.insert((title, year), key);
+        }
+    }
+
+    conflicts
+}
+
+/// A DOI without its resolver URL or `doi:` prefix, lowercased: DOIs are
+/// case-insensitive.
+fn normalize_doi(doi: &str) -> String {
+    let doi = doi.trim().to_lowercase();
+    let doi = ["https://", "http://"]
+        .iter()
+        .find_map(|scheme| doi.strip_prefix(scheme))
+        .unwrap_or(&doi);
+    let doi = ["doi.org/", "dx.doi.org/", "www.doi.org/", "doi:"]
+        .iter()
+        .find_map(|prefix| doi.strip_prefix(prefix))
+        .unwrap_or(doi);
+    doi.trim().to_string()
+}
+
+fn load_bibtex(input: &str) -> Result<LoadedBibliography, String> {
+    use biblatex::ChunksExt;
+
+    // Parsing expands `@string` abbreviations and copies the fields entries
+    // inherit through `crossref` and `xdata` into them, so that grouping
+    // sees their authors and years.
+    let raw_bibliography =
+        biblatex::Bibliography::parse(input).map_err(|e| format!("BibTeX parsing error: {}", e))?;
+    let mut library = from_biblatex(&raw_bibliography)
+        .map_err(|e| format!("BibTeX parsing error: {:?}", e))?;
+    let mut texts: HashMap<String, &str> = HashMap::new();
+    let mut bibtex_macros = Vec::new();
+    for (kind, key, text) in bibtex_blocks(input) {
+        match kind.as_str() {
+            "string" | "preamble" => bibtex_macros.push(text.to_string()),
+            // Field containers, not works: kept for extraction, never cited.
+            "xdata" => {
+                bibtex_macros.push(text.to_string());
+                library.remove(key);
+            }
+            "comment" => {}
+            _ => {
+                texts.insert(key.to_string(), text);
+            }
+        }
+    }
+    let records = raw_bibliography
+        .iter()
+        .filter(|entry| entry.entry_type != biblatex::EntryType::XData)
+        .map(|entry| {
+            let record = SourceRecord {
+                entry_type: entry.entry_type.to_string(),
+                fields: entry
+                    .fields
+                    .iter()
+                    .map(|(name, chunks)| (name.to_lowercase(), chunks.format_verbatim()))
+                    .collect(),
+                bibtex: texts.get(&entry.key).map(|text| text.to_string()),
+                csl: None,
+            };
+            (entry.key.clone(), record)
+        })
+        .collect();
+
+    Ok(LoadedBibliography { library, records, bibtex_macros, ..Default::default() })
+}
+
+/// Splits BibTeX source into its `@type{...}` blocks, as (lowercase type,
+/// key, text). The key is empty for `@string`, `@preamble` and `@comment`.
+fn bibtex_blocks(input: &str) -> Vec<(String, &str, &str)> {
+    let mut blocks = Vec::new();
+    let mut rest = 0;
+    while let Some(offset) = input[rest..].find('@') {
+        let start = rest + offset;
+        let after_at = &input[start + 1..];
+        let kind_len = after_at
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(after_at.len());
+        let kind = after_at[..kind_len].to_lowercase();
+        let opener_offset = after_at[kind_len..].len() - after_at[kind_len..].trim_start().len();
+        let body_start = start + 1 + kind_len + opener_offset;
+        let closer = match input[body_start..].chars().next() {
+            Some('{') => '}',
+            Some('(') => ')',
+            _ => {
+                rest = start + 1;
+                continue;
+            }
+        };
+
+        // Braces nest; a parenthesised block ends at the first `)` outside braces.
+        let mut depth = 0usize;
+        let mut end = None;
+        for (i, c) in input[body_start..].char_indices() {
+            match c {
+                '{' => depth += 1,
+                '}' if closer == '}' && depth == 1 => {
+                    end = Some(body_start + i + 1);
+                    break;
+                }
+                '}' => depth = depth.saturating_sub(1),
+                ')' if closer == ')' && depth == 0 => {
+                    end = Some(body_start + i + 1);
+                    break;
+                }
+                _ => {}
+            }
+        }
+        let Some(end) = end else { break };
+
+        let text = &input[start..end];
+        let key = match kind.as_str() {
+            "string" | "preamble" | "comment" => "",
+            _ => {
+                let body = &input[body_start + 1..end - 1];
+                body.split(',').next().unwrap_or("").trim()
+            }
+        };
+        blocks.push((kind, key, text));
+        rest = end;
+    }
+    blocks
+}
+
+fn load_yaml(input: &str) -> Result<LoadedBibliography, String> {
+    let library =
+        from_yaml_str(input).map_err(|e| format!("Hayagriva YAML parsing error: {}", e))?;
+    // hayagriva has already validated the document, so it parses as plain YAML too.
+    let raw: HashMap<String, serde_yaml::Value> = serde_yaml::from_str(input).unwrap_or_default();
+    let records = raw
+        .iter()
+        .map(|(key, value)| {
+            let fields: HashMap<String, String> = value
+                .as_mapping()
+                .map(|mapping| {
+                    mapping
+                        .iter()
+                        .filter_map(|(name, value)| {
+                            let name = name.as_str()?.to_lowercase();
+                            let value = match value {
+                                serde_yaml::Value::String(s) => s.clone(),
+                                serde_yaml::Value::Number(n) => n.to_string(),
+                                serde_yaml::Value::Bool(b) => b.to_string(),
+                                _ => return None,
+                            };
+                            Some((name, value))
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            let entry_type = fields.get("type").cloned().unwrap_or_else(|| "misc".to_string());
+            (key.clone(), SourceRecord { entry_type, fields, ..Default::default() })
+        })
+        .collect();
+
+    Ok(LoadedBibliography { library, records, ..Default::default() })
+}
+
+fn load_csl_json(input: &str) -> Result<LoadedBibliography, String> {
+    let parsed: Value = serde_json::from_str(input.trim_start_matches('\u{feff}'))
+        .map_err(|e| format!("CSL-JSON parsing error: {}", e))?;
+    let items = match parsed {
+        Value::Array(items) => items,
+        item @ Value::Object(_) => vec![item],
+        _ => return Err("CSL-JSON parsing error: expected an array of items".to_string()),
+    };
+    load_csl_items(&items)
+}
+
+fn load_csl_yaml(input: &str) -> Result<LoadedBibliography, String> {
+    let parsed: serde_yaml::Value =
+        serde_yaml::from_str(input).map_err(|e| format!("CSL-YAML parsing error: {}", e))?;
+    let items = match parsed.get("references").unwrap_or(&parsed) {
+        serde_yaml::Value::Sequence(items) => items
+            .iter()
+            .map(serde_json::to_value)
+            .collect::<Result<Vec<Value>, _>>()
+            .map_err(|e| format!("CSL-YAML parsing error: {}", e))?,
+        _ => return Err("CSL-YAML parsing error: expected a list of references".to_string()),
+    };
+    load_csl_items(&items)
+}
+
+fn load_csl_items(items: &[Value]) -> Result<LoadedBibliography, String> {
+    let mut loaded = LoadedBibliography::default();
+    for item in items {
+        let Some(key) = item.get("id").and_then(value_to_string) else {
+            continue;
+        };
+        let mut single = Map::new();
+        single.insert(key.clone(), csl_item_to_hayagriva(item));
+        let library: Library = serde_json::from_value(Value::Object(single))
+            .map_err(|e| format!("item `{}`: {}", key, e))?;
+        for entry in library.iter() {
+            loaded.library.push(entry);
+        }
+        loaded.records.insert(key, csl_source_record(item));
+    }
+
+    Ok(loaded)
+}
+
+fn csl_source_record(item: &Value) -> SourceRecord {
+    let fields = item
+        .as_object()
+        .map(|object| {
+            object
+                .iter()
+                .filter_map(|(name, value)| Some((name.to_lowercase(), value_to_string(value)?)))
+                .collect()
+        })
+        .unwrap_or_default();
+    SourceRecord {
+        entry_type: item.get("type").and_then(value_to_string).unwrap_or_default(),
+        fields,
+        bibtex: None,
+        csl: Some(item.clone()),
+    }
+}
+
+/// Hayagriva type of a CSL item and, when it has a `container-title`, the
+/// type of the container.
+fn csl_type_to_hayagriva(csl_type: &str) -> (&'static str, &'static str) {
+    match csl_type {
+        "article-journal" | "article-magazine" | "article" | "review" | "review-book" => {
+            ("article", "periodical")
+        }
+        "article-newspaper" => ("article", "newspaper"),
+        "paper-conference" => ("article", "proceedings"),
+        "post-weblog" => ("article", "blog"),
+        "chapter" => ("chapter", "book"),
+        "entry" | "entry-dictionary" | "entry-encyclopedia" => ("entry", "reference"),
+        "book" | "classic" => ("book", "book"),
+        "collection" => ("anthology", "anthology"),
+        "periodical" => ("periodical", "periodical"),
+        "thesis" => ("thesis", "anthology"),
+        "report" | "standard" => ("report", "anthology"),
+        "webpage" => ("web", "web"),
+        "post" => ("post", "thread"),
+        "manuscript" => ("manuscript", "anthology"),
+        "patent" => ("patent", "anthology"),
+        "legal_case" => ("case", "anthology"),
+        "legislation" | "bill" | "regulation" | "treaty" => ("legislation", "anthology"),
+        "motion_picture" | "broadcast" => ("video", "video"),
+        "song" => ("audio", "audio"),
+        "graphic" => ("artwork", "exhibition"),
+        "performance" => ("performance", "anthology"),
+        "event" => ("conference", "anthology"),
+        "software" | "dataset" => ("repository", "anthology"),
+        _ => ("misc", "anthology"),
+    }
+}
+
+/// Builds an entry in hayagriva's YAML data model from a CSL-JSON item.
+fn csl_item_to_hayagriva(item: &Value) -> Value {
+    let csl_type = item.get("type").and_then(Value::as_str).unwrap_or("");
+    let (entry_type, parent_type) = csl_type_to_hayagriva(csl_type);
+    let field = |name: &str| item.get(name).and_then(value_to_string);
+
+    let mut entry = Map::new();
+    entry.insert("type".into(), json!(entry_type));
+    if let Some(title) = field("title") {
+        entry.insert("title".into(), formattable(title, field("title-short")));
+    }
+    for (csl_name, name) in [("author", "author"), ("editor", "editor")] {
+        if let Some(names) = item.get(csl_name).and_then(csl_names) {
+            entry.insert(name.into(), names);
+        }
+    }
+    let affiliated: Vec<Value> = [
+        ("translator", "translator"),
+        ("compiler", "compiler"),
+        ("composer", "composer"),
+        ("director", "director"),
+        ("illustrator", "illustrator"),
+        ("narrator", "narrator"),
+        ("producer", "producer"),
+        ("executive-producer", "executive-producer"),
+    ]
+    .into_iter()
+    .filter_map(|(csl_name, role)| {
+        let names = item.get(csl_name).and_then(csl_names)?;
+        Some(json!({ "role": role, "names": names }))
+    })
+    .collect();
+    if !affiliated.is_empty() {
+        entry.insert("affiliated".into(), Value::Array(affiliated));
+    }
+    if let Some(date) = item.get("issued").and_then(csl_date) {
+        entry.insert("date".into(), json!(date));
+    }
+
+    // Fields that describe the container go to the parent when there is one.
+    let container_title = field("container-title");
+    let mut parent = Map::new();
+    let mut on_container = |name: &str, value: Value, entry: &mut Map<String, Value>| {
+        if container_title.is_some() && matches!(entry_type, "article" | "chapter" | "entry") {
+            parent.insert(name.to_string(), value);
+        } else {
+            entry.insert(name.to_string(), value);
+        }
+    };
+    if let Some(volume) = field("volume") {
+        on_container("volume", json!(volume), &mut entry);
+    }
+    if let Some(issue) = field("issue") {
+        on_container("issue", json!(issue), &mut entry);
+    }
+    if let Some(name) = field("publisher") {
+        let publisher = match field("publisher-place") {
+            Some(location) => json!({ "name": name, "location": location }),
+            None => json!(name),
+        };
+        on_container("publisher", publisher, &mut entry);
+    }
+
+    for (csl_name, name) in [
+        ("page", "page-range"),
+        ("number-of-pages", "page-total"),
+        ("number-of-volumes", "volume-total"),
+        ("edition", "edition"),
+        ("chapter-number", "chapter"),
+        ("genre", "genre"),
+        ("abstract", "abstract"),
+        ("note", "note"),
+        ("language", "language"),
+        ("archive", "archive"),
+        ("archive_location", "archive-location"),
+        ("call-number", "call-number"),
+        ("event-place", "location"),
+        ("authority", "organization"),
+    ] {
+        if let Some(value) = field(csl_name) {
+            entry.insert(name.into(), json!(value));
+        }
+    }
+
+    if let Some(url) = field("URL") {
+        let url = match item.get("accessed").and_then(csl_date) {
+            Some(date) => json!({ "value": url, "date": date }),
+            None => json!(url),
+        };
+        entry.insert("url".into(), url);
+    }
+
+    let mut serial_numbers = Map::new();
+    for (csl_name, name) in [
+        ("DOI", "doi"),
+        ("ISBN", "isbn"),
+        ("ISSN", "issn"),
+        ("PMID", "pmid"),
+        ("PMCID", "pmcid"),
+        ("number", "serial"),
+    ] {
+        if let Some(value) = field(csl_name) {
+            serial_numbers.insert(name.into(), json!(value));
+        }
+    }
+    if !serial_numbers.is_empty() {
+        entry.insert("serial-number".into(), Value::Object(serial_numbers));
+    }
+
+    if let Some(title) = container_title {
+        parent.insert("type".into(), json!(parent_type));
+        parent.insert("title".into(), formattable(title, field("container-title-short")));
+    }
+    if let Some(series_title) = field("collection-title") {
+        let mut series = Map::new();
+        series.insert("title".into(), json!(series_title));
+        if let Some(number) = field("collection-number") {
+            series.insert("issue".into(), json!(number));
+        }
+        if parent.is_empty() {
+            parent = series;
+        } else {
+            parent.insert("parent".into(), Value::Object(series));
+        }
+    }
+    if !parent.is_empty() {
+        entry.insert("parent".into(), Value::Object(parent));
+    }
+
+    Value::Object(entry)
+}
+
+/// Gives an item without an `id` one made of its first author's family name
+/// and its year, e.g. `cohen1963`, unique among `taken`.
+fn assign_key(item: &mut Map<String, Value>, taken: &mut HashSet<String>) -> String {
+    if let Some(key) = item.get("id").and_then(value_to_string) {
+        taken.insert(key.clone());
+        return key;
+    }
+
+    let name = item
+        .get("author")
+        .or_else(|| item.get("editor"))
+        .and_then(|names| names.get(0))
+        .and_then(|name| name.get("family").or_else(|| name.get("literal")))
+        .and_then(value_to_string)
+        .unwrap_or_else(|| "anon".to_string());
+    let year = item
+        .get("issued")
+        .and_then(csl_date)
+        .map(|date| date.chars().take(4).collect::<String>())
+        .unwrap_or_default();
+    let base: String = format!("{}{}", name, year)
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect();
+
+    let mut key = base.clone();
+    let mut suffix = b'a';
+    while taken.contains(&key) && suffix <= b'z' {
+        key = format!("{}{}", base, suffix as char);
+        suffix += 1;
+    }
+    taken.insert(key.clone());
+    item.insert("id".into(), json!(key));
+    key
+}
+
+/// The fields an importer dropped from the item `item`, keyed by its `id`.
+fn unmapped_fields(item: &Value, dropped: Vec<(String, String)>) -> Vec<UnmappedField> {
+    let key = item.get("id").and_then(Value::as_str).unwrap_or_default();
+    dropped
+        .into_iter()
+        .map(|(field, value)| UnmappedField { key: key.to_string(), field, value })
+        .collect()
+}
+
+/// Sets a variable unless an earlier field already did.
+fn set_variable(item: &mut Map<String, Value>, name: &str, value: &str) {
+    item.entry(name.to_string()).or_insert_with(|| json!(value));
+}
+
+/// Adds a name to the list of the name variable `variable`, keeping the
+/// variables in the order they first appear.
+fn push_name(names: &mut Vec<(&'static str, Vec<Value>)>, variable: &'static str, text: &str) {
+    let name = csl_name_from_text(text);
+    match names.iter_mut().find(|(v, _)| *v == variable) {
+        Some((_, list)) => list.push(name),
+        None => names.push((variable, vec![name])),
+    }
+}
+
+/// Sets the name variables collected by [`push_name`].
+fn insert_names(item: &mut Map<String, Value>, names: Vec<(&'static str, Vec<Value>)>) {
+    for (variable, list) in names {
+        item.insert(variable.into(), Value::Array(list));
+    }
+}
+
+/// Converts a name written as `Family, Given[, Suffix]` to a CSL name; names
+/// without a comma, such as organisations, are kept literally.
+fn csl_name_from_text(text: &str) -> Value {
+    let parts: Vec<&str> = text.split(',').map(str::trim).collect();
+    match parts.as_slice() {
+        [family, given] => json!({ "family": family, "given": given }),
+        [family, given, suffix, ..] => json!({ "family": family, "given": given, "suffix": suffix }),
+        _ => json!({ "literal": text.trim() }),
+    }
+}
+
+/// Converts a date written as `YYYY/MM/DD/other` or `YYYY-MM-DD` to a CSL date.
+fn csl_date_from_text(text: &str) -> Option<Value> {
+    let parts: Vec<i64> = text
+        .split(['/', '-'])
+        .map_while(|part| part.trim().parse().ok())
+        .take(3)
+        .collect();
+    (!parts.is_empty()).then(|| json!({ "date-parts": [parts] }))
+}
+
+fn formattable(value: String, short: Option<String>) -> Value {
+    match short {
+        Some(short) => json!({ "value": value, "short": short }),
+        None => json!(value),
+    }
+}
+
+/// Converts CSL name variables to hayagriva persons.
+fn csl_names(names: &Value) -> Option<Value> {
+    let persons: Vec<Value> = names
+        .as_array()?
+        .iter()
+        .filter_map(|name| {
+            if let Some(literal) = name.get("literal").and_then(value_to_string) {
+                return Some(json!({ "name": literal }));
+            }
+            let family = name.get("family").and_then(value_to_string)?;
+            let mut person = Map::new();
+            person.insert("name".into(), json!(family));
+            if let Some(given) = name.get("given").and_then(value_to_string) {
+                person.insert("given-name".into(), json!(given));
+            }
+            let particles: Vec<String> = ["dropping-particle", "non-dropping-particle"]
+                .iter()
+                .filter_map(|particle| name.get(*particle).and_then(value_to_string))
+                .collect();
+            if !particles.is_empty() {
+                person.insert("prefix".into(), json!(particles.join(" ")));
+            }
+            if let Some(suffix) = name.get("suffix").and_then(value_to_string) {
+                person.insert("suffix".into(), json!(suffix));
+            }
+            Some(Value::Object(person))
+        })
+        .collect();
+    (!persons.is_empty()).then_some(Value::Array(persons))
+}
+
+/// Converts a CSL date (`date-parts`, or a `raw`/`literal` string starting
+/// with an ISO date or a year) to an ISO 8601 date.
+fn csl_date(date: &Value) -> Option<String> {
+    if let Some(parts) = date
+        .get("date-parts")
+        .and_then(Value::as_array)
+        .and_then(|ranges| ranges.first())
+        .and_then(Value::as_array)
+    {
+        let numbers: Vec<i64> = parts
+            .iter()
+            .filter_map(|part| value_to_string(part)?.trim().parse().ok())
+            .collect();
+        return match numbers.as_slice() {
+            [] => None,
+            [year] => Some(format!("{:04}", year)),
+            [year, month] => Some(format!("{:04}-{:02}", year, month)),
+            [year, month, day, ..] => Some(format!("{:04}-{:02}-{:02}", year, month, day)),
+        };
+    }
+
+    let text = ["raw", "literal"]
+        .iter()
+        .find_map(|name| date.get(*name).and_then(Value::as_str))
+        .or_else(|| date.as_str())?;
+    let iso: String = text
+        .trim()
+        .chars()
+        .take_while(|c| c.is_ascii_digit() || *c == '-')
+        .collect();
+    let iso = iso.trim_end_matches('-');
+    (iso.len() >= 4).then(|| iso.to_string())
+}
+
+/// Reads strings and numbers as text; CSL-JSON allows both for most variables.
+fn value_to_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) if !s.trim().is_empty() => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
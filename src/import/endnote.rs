@@ -0,0 +1,198 @@
+//! Translates EndNote XML exports (`<xml><records><record>...`) to CSL items.
+
+use super::{
+    assign_key, csl_date_from_text, insert_names, push_name, set_variable, unmapped_fields,
+    UnmappedField,
+};
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use serde_json::{json, Map, Value};
+use std::collections::HashSet;
+
+/// A record as its element paths and text, e.g. `("titles/title", "...")`.
+struct Record {
+    ref_type: String,
+    fields: Vec<(String, String)>,
+}
+
+/// CSL type of an EndNote reference type name.
+fn csl_type(ref_type: &str) -> Option<&'static str> {
+    Some(match ref_type {
+        "Journal Article" | "Electronic Article" => "article-journal",
+        "Magazine Article" => "article-magazine",
+        "Newspaper Article" => "article-newspaper",
+        "Book" | "Edited Book" | "Electronic Book" => "book",
+        "Book Section" | "Electronic Book Section" => "chapter",
+        "Conference Proceedings" | "Conference Paper" => "paper-conference",
+        "Thesis" => "thesis",
+        "Report" | "Standard" => "report",
+        "Web Page" => "webpage",
+        "Blog" => "post-weblog",
+        "Patent" => "patent",
+        "Computer Program" => "software",
+        "Dataset" => "dataset",
+        "Case" => "legal_case",
+        "Statute" | "Bill" | "Government Document" => "legislation",
+        "Film or Broadcast" => "motion_picture",
+        "Encyclopedia" => "entry-encyclopedia",
+        "Dictionary" => "entry-dictionary",
+        "Manuscript" | "Unpublished Work" => "manuscript",
+        "Generic" => "document",
+        _ => return None,
+    })
+}
+
+/// Parses the records of an EndNote XML export into CSL items, with the
+/// elements that had no CSL equivalent.
+pub(super) fn parse(input: &str) -> Result<(Vec<Value>, Vec<UnmappedField>), String> {
+    let error = |e: &dyn std::fmt::Display| format!("EndNote XML parsing error: {}", e);
+    let mut reader = Reader::from_str(input.trim_start_matches('\u{feff}'));
+    reader.config_mut().trim_text(true);
+
+    let mut records = Vec::new();
+    let mut record: Option<Record> = None;
+    // Element names below `<record>`; `<style>` only carries formatting.
+    let mut path: Vec<String> = Vec::new();
+    let mut text = String::new();
+
+    loop {
+        match reader.read_event().map_err(|e| error(&e))? {
+            Event::Start(element) => {
+                let name = String::from_utf8_lossy(element.name().as_ref()).into_owned();
+                if name == "record" {
+                    record = Some(Record { ref_type: String::new(), fields: Vec::new() });
+                } else if let Some(record) = record.as_mut() {
+                    if name == "ref-type" {
+                        if let Some(attribute) =
+                            element.try_get_attribute("name").map_err(|e| error(&e))?
+                        {
+                            record.ref_type =
+                                attribute.unescape_value().map_err(|e| error(&e))?.into_owned();
+                        }
+                    }
+                    if name != "style" {
+                        path.push(name);
+                    }
+                }
+            }
+            Event::Text(content) if record.is_some() => {
+                text.push_str(&content.unescape().map_err(|e| error(&e))?);
+            }
+            Event::CData(content) if record.is_some() => {
+                text.push_str(&String::from_utf8_lossy(&content));
+            }
+            Event::End(element) => {
+                let name = element.name();
+                if name.as_ref() == b"record" {
+                    records.extend(record.take());
+                    path.clear();
+                } else if let Some(record) = record.as_mut() {
+                    if name.as_ref() != b"style" {
+                        let value = text.trim();
+                        if !value.is_empty() {
+                            record.fields.push((path.join("/"), value.to_string()));
+                        }
+                        text.clear();
+                        path.pop();
+                    }
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    let mut items = Vec::new();
+    let mut unmapped = Vec::new();
+    let mut taken = HashSet::new();
+    for record in &records {
+        let (item, dropped) = record_to_item(record, &mut taken);
+        unmapped.extend(unmapped_fields(&item, dropped));
+        items.push(item);
+    }
+    Ok((items, unmapped))
+}
+
+/// Builds a CSL item from one record, returning the elements it dropped.
+fn record_to_item(record: &Record, taken: &mut HashSet<String>) -> (Value, Vec<(String, String)>) {
+    let item_type = csl_type(&record.ref_type);
+    let is_book = matches!(item_type, Some("book"));
+
+    let mut item = Map::new();
+    item.insert("type".into(), json!(item_type.unwrap_or("document")));
+    let mut dropped = Vec::new();
+    if item_type.is_none() && !record.ref_type.is_empty() {
+        dropped.push(("ref-type".to_string(), record.ref_type.clone()));
+    }
+
+    let mut names = Vec::new();
+    let mut keywords = Vec::new();
+
+    for (path, value) in &record.fields {
+        let name_variable = match path.as_str() {
+            "contributors/authors/author" => Some("author"),
+            "contributors/secondary-authors/author" => Some("editor"),
+            "contributors/subsidiary-authors/author" | "contributors/translated-authors/author" => {
+                Some("translator")
+            }
+            _ => None,
+        };
+        if let Some(variable) = name_variable {
+            push_name(&mut names, variable, value);
+            continue;
+        }
+
+        match path.as_str() {
+            "rec-number" | "ref-type" | "database" | "source-app" | "foreign-keys/key" => {}
+            "titles/title" => set_variable(&mut item, "title", value),
+            "titles/short-title" => set_variable(&mut item, "title-short", value),
+            "titles/secondary-title" if is_book => {
+                set_variable(&mut item, "collection-title", value)
+            }
+            "titles/secondary-title" | "periodical/full-title" => {
+                set_variable(&mut item, "container-title", value)
+            }
+            "titles/alt-title" | "periodical/abbr-1" => {
+                set_variable(&mut item, "container-title-short", value)
+            }
+            "titles/tertiary-title" => set_variable(&mut item, "collection-title", value),
+            "pages" => set_variable(&mut item, "page", value),
+            "volume" => set_variable(&mut item, "volume", value),
+            "number" => set_variable(&mut item, "issue", value),
+            "edition" => set_variable(&mut item, "edition", value),
+            "publisher" => set_variable(&mut item, "publisher", value),
+            "pub-location" => set_variable(&mut item, "publisher-place", value),
+            "isbn" => set_variable(&mut item, if is_book { "ISBN" } else { "ISSN" }, value),
+            "electronic-resource-num" => {
+                set_variable(&mut item, "DOI", value.trim_start_matches("https://doi.org/"))
+            }
+            "urls/related-urls/url" | "urls/web-urls/url" => set_variable(&mut item, "URL", value),
+            "abstract" => set_variable(&mut item, "abstract", value),
+            "notes" => set_variable(&mut item, "note", value),
+            "language" => set_variable(&mut item, "language", value),
+            "call-num" => set_variable(&mut item, "call-number", value),
+            "work-type" => set_variable(&mut item, "genre", value),
+            "keywords/keyword" => keywords.push(value.as_str()),
+            "dates/year" => match csl_date_from_text(value) {
+                Some(date) => {
+                    item.insert("issued".into(), date);
+                }
+                None => dropped.push((path.clone(), value.clone())),
+            },
+            "access-date" => {
+                if let Some(date) = csl_date_from_text(value) {
+                    item.insert("accessed".into(), date);
+                }
+            }
+            _ => dropped.push((path.clone(), value.clone())),
+        }
+    }
+
+    insert_names(&mut item, names);
+    if !keywords.is_empty() {
+        item.insert("keyword".into(), json!(keywords.join(", ")));
+    }
+
+    assign_key(&mut item, taken);
+    (Value::Object(item), dropped)
+}
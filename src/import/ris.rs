@@ -0,0 +1,178 @@
+//! Translates RIS records (`TY  - JOUR` ... `ER  -`) to CSL items.
+
+use super::{
+    assign_key, csl_date_from_text, insert_names, push_name, set_variable, unmapped_fields,
+    UnmappedField,
+};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde_json::{json, Map, Value};
+use std::collections::HashSet;
+
+static TAG_PATTERN: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^([A-Z][A-Z0-9])\s{1,2}-(?:\s(.*))?$").unwrap());
+
+/// CSL type of a RIS reference type.
+fn csl_type(ris_type: &str) -> Option<&'static str> {
+    Some(match ris_type {
+        "JOUR" | "JFULL" | "EJOUR" | "ABST" | "INPR" => "article-journal",
+        "MGZN" => "article-magazine",
+        "NEWS" => "article-newspaper",
+        "BOOK" | "EBOOK" | "EDBOOK" | "CTLG" => "book",
+        "CHAP" | "ECHAP" => "chapter",
+        "CONF" | "CPAPER" => "paper-conference",
+        "THES" => "thesis",
+        "RPRT" | "STAND" => "report",
+        "ELEC" | "WEB" | "BLOG" => "webpage",
+        "PAT" => "patent",
+        "COMP" => "software",
+        "DATA" | "DBASE" => "dataset",
+        "CASE" => "legal_case",
+        "STAT" | "BILL" | "GOVDOC" => "legislation",
+        "VIDEO" | "MPCT" => "motion_picture",
+        "SOUND" | "MUSIC" => "song",
+        "ART" | "FIGURE" => "graphic",
+        "ENCYC" | "DICT" => "entry-encyclopedia",
+        "MANSCPT" | "UNPB" => "manuscript",
+        "GEN" => "document",
+        _ => return None,
+    })
+}
+
+/// Parses RIS records into CSL items, with the tags that had no CSL equivalent.
+pub(super) fn parse(input: &str) -> (Vec<Value>, Vec<UnmappedField>) {
+    let mut items = Vec::new();
+    let mut unmapped = Vec::new();
+    let mut taken = HashSet::new();
+    let mut fields: Vec<(String, String)> = Vec::new();
+
+    for line in input.trim_start_matches('\u{feff}').lines() {
+        let line = line.trim_end();
+        match TAG_PATTERN.captures(line) {
+            Some(cap) if &cap[1] == "ER" => {
+                let (item, dropped) = record_to_item(&fields, &mut taken);
+                unmapped.extend(unmapped_fields(&item, dropped));
+                items.push(item);
+                fields.clear();
+            }
+            Some(cap) => {
+                let value = cap.get(2).map_or("", |m| m.as_str()).trim();
+                fields.push((cap[1].to_string(), value.to_string()));
+            }
+            // Continuation of a wrapped value.
+            None if !line.is_empty() => {
+                if let Some((_, value)) = fields.last_mut() {
+                    value.push(' ');
+                    value.push_str(line.trim());
+                }
+            }
+            None => {}
+        }
+    }
+
+    (items, unmapped)
+}
+
+/// Builds a CSL item from the tags of one record, returning the tags it dropped.
+fn record_to_item(
+    fields: &[(String, String)],
+    taken: &mut HashSet<String>,
+) -> (Value, Vec<(String, String)>) {
+    let ris_type = fields
+        .iter()
+        .find(|(tag, _)| tag == "TY")
+        .map_or("GEN", |(_, value)| value.as_str());
+    let item_type = csl_type(ris_type);
+    let is_book = matches!(item_type, Some("book"));
+
+    let mut item = Map::new();
+    item.insert("type".into(), json!(item_type.unwrap_or("document")));
+    let mut dropped = Vec::new();
+    if item_type.is_none() {
+        dropped.push(("TY".to_string(), ris_type.to_string()));
+    }
+
+    let mut names = Vec::new();
+    let mut keywords = Vec::new();
+    let mut urls = Vec::new();
+    let (mut start_page, mut end_page) = (None, None);
+
+    for (tag, value) in fields {
+        if value.is_empty() {
+            continue;
+        }
+        let name_variable = match tag.as_str() {
+            "AU" | "A1" => Some("author"),
+            "A2" | "ED" => Some("editor"),
+            "A4" => Some("translator"),
+            _ => None,
+        };
+        if let Some(variable) = name_variable {
+            push_name(&mut names, variable, value);
+            continue;
+        }
+
+        match tag.as_str() {
+            "TY" => {}
+            "ID" => set_variable(&mut item, "id", value),
+            "TI" | "T1" => set_variable(&mut item, "title", value),
+            "BT" if is_book => set_variable(&mut item, "title", value),
+            "ST" => set_variable(&mut item, "title-short", value),
+            "T2" | "JF" | "JO" | "BT" => set_variable(&mut item, "container-title", value),
+            "J2" | "JA" => set_variable(&mut item, "container-title-short", value),
+            "T3" => set_variable(&mut item, "collection-title", value),
+            "VL" => set_variable(&mut item, "volume", value),
+            "IS" => set_variable(&mut item, "issue", value),
+            "SP" => start_page = Some(value.as_str()),
+            "EP" => end_page = Some(value.as_str()),
+            "PB" => set_variable(&mut item, "publisher", value),
+            "CY" | "PP" => set_variable(&mut item, "publisher-place", value),
+            "ET" => set_variable(&mut item, "edition", value),
+            "M3" => set_variable(&mut item, "genre", value),
+            "SN" => set_variable(&mut item, if is_book { "ISBN" } else { "ISSN" }, value),
+            "DO" => set_variable(&mut item, "DOI", value.trim_start_matches("https://doi.org/")),
+            "UR" | "L2" => urls.push(value.as_str()),
+            "AB" | "N2" => set_variable(&mut item, "abstract", value),
+            "N1" => set_variable(&mut item, "note", value),
+            "LA" => set_variable(&mut item, "language", value),
+            "CN" => set_variable(&mut item, "call-number", value),
+            "KW" => keywords.push(value.as_str()),
+            "PY" | "Y1" | "DA" => {
+                if !item.contains_key("issued") {
+                    match csl_date_from_text(value) {
+                        Some(date) => {
+                            item.insert("issued".into(), date);
+                        }
+                        None => dropped.push((tag.clone(), value.clone())),
+                    }
+                }
+            }
+            "Y2" => {
+                if let Some(date) = csl_date_from_text(value) {
+                    item.entry("accessed").or_insert(date);
+                }
+            }
+            _ => dropped.push((tag.clone(), value.clone())),
+        }
+    }
+
+    insert_names(&mut item, names);
+    if !keywords.is_empty() {
+        item.insert("keyword".into(), json!(keywords.join(", ")));
+    }
+    if let Some(url) = urls.first() {
+        item.insert("URL".into(), json!(url));
+    }
+    match (start_page, end_page) {
+        (Some(start), Some(end)) => {
+            item.insert("page".into(), json!(format!("{}-{}", start, end)));
+        }
+        (Some(page), None) | (None, Some(page)) => {
+            item.insert("page".into(), json!(page));
+        }
+        (None, None) => {}
+    }
+
+    assign_key(&mut item, taken);
+    (Value::Object(item), dropped)
+}
@@ -0,0 +1,271 @@
+//! LaTeX markup in names and titles, and the plain forms used for matching.
+//!
+//! BibTeX sources arrive mostly decoded by `biblatex`, but leave accents as
+//! combining sequences (`\'{\i}` becomes a dotless i and an acute); YAML and
+//! CSL sources are not decoded at all, so `G{\"o}del` stays as written. Both
+//! end up as composed Unicode (`Gödel`), and [`ascii_fold`] reduces that to
+//! what can be typed in a citation key (`Godel`).
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+static TRAILING_ACCENT_PATTERN: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"\\(?:["'`^~=.]|[uvHcdkbr])\s*$"#).unwrap());
+
+/// Converts LaTeX accent commands, special characters and grouping braces to
+/// Unicode, in NFC. Text without a `\` or `{` is only recomposed, so that
+/// `~` and `--` in already decoded text are left alone.
+pub(crate) fn to_unicode(text: &str) -> String {
+    let decoded = if text.contains(['\\', '{']) {
+        let chars: Vec<char> = text.chars().collect();
+        let mut pos = 0;
+        let decoded = decode(&chars, &mut pos, false);
+        decoded.replace("---", "—").replace("--", "–")
+    } else {
+        text.to_string()
+    };
+    compose(&decoded)
+}
+
+/// Splits an accent command without its argument off the end of the text:
+/// hayagriva reads the braces of `\"{o}` as a separate chunk.
+pub(crate) fn split_trailing_accent(text: &str) -> (&str, &str) {
+    match TRAILING_ACCENT_PATTERN.find(text) {
+        Some(accent) => text.split_at(accent.start()),
+        None => (text, ""),
+    }
+}
+
+/// Strips diacritics and spells out letters without an ASCII base, such as
+/// `ł`, `ø` and `ß`; other non-ASCII characters are kept.
+pub(crate) fn ascii_fold(text: &str) -> String {
+    let mut folded = String::with_capacity(text.len());
+    for c in text.nfd().filter(|c| !is_combining_mark(*c)) {
+        match c {
+            'ł' => folded.push('l'),
+            'Ł' => folded.push('L'),
+            'ø' => folded.push('o'),
+            'Ø' => folded.push('O'),
+            'đ' | 'ð' => folded.push('d'),
+            'Đ' | 'Ð' => folded.push('D'),
+            'ı' => folded.push('i'),
+            'ȷ' => folded.push('j'),
+            'ŋ' => folded.push('n'),
+            'Ŋ' => folded.push('N'),
+            'æ' => folded.push_str("ae"),
+            'Æ' => folded.push_str("AE"),
+            'œ' => folded.push_str("oe"),
+            'Œ' => folded.push_str("OE"),
+            'ß' => folded.push_str("ss"),
+            'ẞ' => folded.push_str("SS"),
+            'þ' => folded.push_str("th"),
+            'Þ' => folded.push_str("TH"),
+            '\u{a0}' => folded.push(' '),
+            '–' | '—' => folded.push('-'),
+            _ => folded.push(c),
+        }
+    }
+    folded
+}
+
+/// NFC, with a dotless `ı` or `ȷ` under an accent restored to `i` or `j`
+/// (LaTeX writes `\'{\i}` for `í`).
+fn compose(text: &str) -> String {
+    let mut restored = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let accented = chars.peek().is_some_and(|next| is_combining_mark(*next));
+        restored.push(match c {
+            'ı' if accented => 'i',
+            'ȷ' if accented => 'j',
+            _ => c,
+        });
+    }
+    restored.nfc().collect()
+}
+
+/// Decodes from `pos` to the end of the text or, inside a group, to its
+/// closing brace, which is consumed.
+fn decode(chars: &[char], pos: &mut usize, in_group: bool) -> String {
+    let mut out = String::new();
+    while let Some(&c) = chars.get(*pos) {
+        *pos += 1;
+        match c {
+            '{' => out.push_str(&decode(chars, pos, true)),
+            '}' if in_group => return out,
+            '}' => {}
+            '~' => out.push('\u{a0}'),
+            '\\' => out.push_str(&command(chars, pos)),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Decodes the command after a backslash, with its argument.
+fn command(chars: &[char], pos: &mut usize) -> String {
+    let Some(&first) = chars.get(*pos) else {
+        return "\\".to_string();
+    };
+    *pos += 1;
+    let name: String = if first.is_ascii_alphabetic() {
+        let mut name = first.to_string();
+        while let Some(&c) = chars.get(*pos).filter(|c| c.is_ascii_alphabetic()) {
+            name.push(c);
+            *pos += 1;
+        }
+        // A control word swallows the spaces after it.
+        while chars.get(*pos) == Some(&' ') {
+            *pos += 1;
+        }
+        name
+    } else {
+        first.to_string()
+    };
+
+    if let Some(mark) = accent(&name) {
+        let base = argument(chars, pos).unwrap_or_default();
+        let mut base = base.chars();
+        return match base.next() {
+            Some(c) => format!("{}{}{}", c, mark, base.as_str()),
+            None => mark.to_string(),
+        };
+    }
+    if let Some(letter) = special(&name) {
+        // `\ss{}` and `{\ss}` are both common.
+        if chars.get(*pos) == Some(&'{') && chars.get(*pos + 1) == Some(&'}') {
+            *pos += 2;
+        }
+        return letter.to_string();
+    }
+    match name.as_str() {
+        "&" | "%" | "$" | "#" | "_" | "{" | "}" => name,
+        "\\" | " " | "," => " ".to_string(),
+        // A hyphenation point.
+        "-" => String::new(),
+        "textendash" => "–".to_string(),
+        "textemdash" => "—".to_string(),
+        // Formatting commands keep their argument, or vanish without one.
+        "emph" | "textit" | "textbf" | "textsc" | "textsl" | "textup" | "textrm" | "textsf"
+        | "texttt" | "textnormal" | "mbox" | "relax"
+            if chars.get(*pos) != Some(&'{') =>
+        {
+            String::new()
+        }
+        // Formatting commands keep their argument.
+        _ if chars.get(*pos) == Some(&'{') => {
+            *pos += 1;
+            decode(chars, pos, true)
+        }
+        _ => format!("\\{}", name),
+    }
+}
+
+/// The argument of an accent: a group, a command such as `\i`, or one
+/// character.
+fn argument(chars: &[char], pos: &mut usize) -> Option<String> {
+    let &c = chars.get(*pos)?;
+    *pos += 1;
+    Some(match c {
+        '{' => decode(chars, pos, true),
+        '\\' => command(chars, pos),
+        _ => c.to_string(),
+    })
+}
+
+/// The combining mark of an accent command.
+fn accent(name: &str) -> Option<char> {
+    Some(match name {
+        "`" => '\u{300}',
+        "'" => '\u{301}',
+        "^" => '\u{302}',
+        "~" => '\u{303}',
+        "=" => '\u{304}',
+        "u" => '\u{306}',
+        "." => '\u{307}',
+        "\"" => '\u{308}',
+        "r" => '\u{30a}',
+        "H" => '\u{30b}',
+        "v" => '\u{30c}',
+        "d" => '\u{323}',
+        "c" => '\u{327}',
+        "k" => '\u{328}',
+        "b" => '\u{331}',
+        _ => return None,
+    })
+}
+
+/// Letters and symbols written as a command without an argument.
+fn special(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "ss" => "ß",
+        "SS" => "ẞ",
+        "o" => "ø",
+        "O" => "Ø",
+        "l" => "ł",
+        "L" => "Ł",
+        "ae" => "æ",
+        "AE" => "Æ",
+        "oe" => "œ",
+        "OE" => "Œ",
+        "aa" => "å",
+        "AA" => "Å",
+        "i" => "ı",
+        "j" => "ȷ",
+        "dh" => "ð",
+        "DH" => "Ð",
+        "dj" => "đ",
+        "DJ" => "Đ",
+        "ng" => "ŋ",
+        "NG" => "Ŋ",
+        "th" => "þ",
+        "TH" => "Þ",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ascii_fold, split_trailing_accent, to_unicode};
+
+    const NAMES: &[(&str, &str, &str)] = &[
+        (r#"G{\"o}del"#, "Gödel", "Godel"),
+        (r"{\L}ukasiewicz", "Łukasiewicz", "Lukasiewicz"),
+        (r"Mart{\'{\i}}nez", "Martínez", "Martinez"),
+        (r"Mart\'{\i}nez", "Martínez", "Martinez"),
+        (r"Fran\c{c}ois", "François", "Francois"),
+        (r"Erd{\H o}s", "Erdős", "Erdos"),
+        (r"Stra{\ss}e", "Straße", "Strasse"),
+        ("Gödel", "Gödel", "Godel"),
+    ];
+
+    #[test]
+    fn names_decode_to_unicode_and_fold_to_ascii() {
+        for &(latex, unicode, folded) in NAMES {
+            assert_eq!(to_unicode(latex), unicode, "input: {:?}", latex);
+            assert_eq!(ascii_fold(&to_unicode(latex)), folded, "input: {:?}", latex);
+        }
+        assert_eq!(ascii_fold(&to_unicode(r#"G{\"o}del"#)).to_lowercase(), "godel");
+        assert_eq!(ascii_fold(&to_unicode(r"{\L}ukasiewicz")).to_lowercase(), "lukasiewicz");
+    }
+
+    #[test]
+    fn decoded_text_is_left_alone() {
+        // Without a `\` or `{`, `~` and `--` are not LaTeX.
+        assert_eq!(to_unicode("pages 1--2 ~ approx."), "pages 1--2 ~ approx.");
+        assert_eq!(to_unicode(r"\emph{Principia} --- 1910"), "Principia — 1910");
+        // Combining sequences from biblatex are composed.
+        assert_eq!(to_unicode("G\u{f6}del"), "Gödel");
+        assert_eq!(to_unicode("Mart\u{131}\u{301}nez"), "Martínez");
+    }
+
+    #[test]
+    fn accent_split_from_its_argument() {
+        assert_eq!(split_trailing_accent(r#"G\""#), ("G", r#"\""#));
+        assert_eq!(split_trailing_accent(r"Fran\c "), ("Fran", r"\c "));
+        assert_eq!(split_trailing_accent("Plain"), ("Plain", ""));
+        assert_eq!(split_trailing_accent(r"\'\i"), (r"\'\i", ""));
+    }
+}
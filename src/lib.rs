@@ -1,731 +1,862 @@
-use hayagriva::io::from_biblatex_str;
 use hayagriva::{
     BibliographyDriver, BibliographyRequest, CitationItem, CitationRequest, Entry,
 };
-use hayagriva::citationberg::{IndependentStyle, Locale, LocaleFile};
+use hayagriva::citationberg::taxonomy::{OtherTerm, Term};
+use hayagriva::citationberg::{
+    IndependentStyle, Locale, LocaleCode, LocaleFile, StyleClass, TermForm,
+};
 use hayagriva::types::Person;
 use regex::{Captures, Regex};
 use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+mod embedded;
+mod export;
+mod front_matter;
+mod import;
+mod latex;
+mod repair;
+mod sections;
+
+pub use front_matter::FrontMatter;
+pub use repair::{
+    parse_incomplete_markdown, repair_markdown, repair_markdown_with_edits, RepairEdit,
+    RepairEditKind, RepairOptions, RepairRules, RepairedView, StreamingRepair,
+};
+
+pub use import::{
+    BibliographyFormat, BibliographySource, DuplicatePrecedence, MergeConflict, UnmappedField,
+};
 
 // For fuzzy matching
 use levenshtein::levenshtein;
 
+/// Grouping key for BibTeX entries: (first author last name, ASCII-folded and
+/// lowercased, two-digit year).
+type EntryGroupKey = (String, String);
+
 pub struct ProcessingOutput {
     pub modified_markdown: String,
     pub bibliography_markdown: String,
+    /// Fields of RIS or EndNote XML records that could not be translated.
+    pub unmapped_fields: Vec<UnmappedField>,
+    /// Duplicates found while merging several bibliographies.
+    pub merge_conflicts: Vec<MergeConflict>,
+    /// The entries of all bibliographies, in the requested
+    /// [`ProcessingOptions::export_format`].
+    pub exported_bibliography: Option<String>,
 }
 
-
-use once_cell::sync::Lazy;
-
-
-
-// Statically compile regex patterns for performance.
-
-// `once_cell::sync::Lazy` ensures this is done only once, safely across threads.
-
-static LINK_IMAGE_PATTERN: Lazy<Regex> =
-
-    Lazy::new(|| Regex::new(r"(!?\[)([^\]]*?)$").unwrap());
-
-static BOLD_PATTERN: Lazy<Regex> = Lazy::new(|| Regex::new(r"(\*\*)([^*]*?)$").unwrap());
-
-static ITALIC_PATTERN: Lazy<Regex> = Lazy::new(|| Regex::new(r"(__)([^_]*?)$").unwrap());
-
-static BOLD_ITALIC_PATTERN: Lazy<Regex> =
-
-    Lazy::new(|| Regex::new(r"(\*\*\*)([^*]*?)$").unwrap());
-
-static SINGLE_ASTERISK_PATTERN: Lazy<Regex> =
-
-    Lazy::new(|| Regex::new(r"(\*)([^*]*?)$").unwrap());
-
-static SINGLE_UNDERSCORE_PATTERN: Lazy<Regex> =
-
-    Lazy::new(|| Regex::new(r"(_)([^_]*?)$").unwrap());
-
-static INLINE_CODE_PATTERN: Lazy<Regex> = Lazy::new(|| Regex::new(r"(`)([^`]*?)$").unwrap());
-
-static STRIKETHROUGH_PATTERN: Lazy<Regex> =
-
-    Lazy::new(|| Regex::new(r"(~~)([^~]*?)$").unwrap());
-
-
-
-// A regex to check for content that is only whitespace or other emphasis markers.
-
-static MEANINGLESS_CONTENT_PATTERN: Lazy<Regex> =
-
-    Lazy::new(|| Regex::new(r"^[\s_~*`]*$").unwrap());
-
-
-
-/// Helper function to check if we have a complete code block.
-
-fn has_complete_code_block(text: &str) -> bool {
-
-    let triple_backticks = text.matches("```").count();
-
-    triple_backticks > 0 && triple_backticks % 2 == 0 && text.contains('\n')
-
-}
-
-
-
-/// Handles incomplete links and images by preserving them with a special marker.
-
-fn handle_incomplete_links_and_images(text: &str) -> String {
-
-    if let Some(captures) = LINK_IMAGE_PATTERN.captures(text) {
-
-        let link_match = captures.get(0).unwrap();
-
-        let is_image = captures.get(1).unwrap().as_str().starts_with('!');
-
-
-
-        // For images, remove them as they can't show a skeleton UI.
-
-        if is_image {
-
-            return text[..link_match.start()].to_string();
-
-        }
-
-
-
-        // For links, preserve the text and close the link with a special placeholder.
-
-        return format!("{text}](streamdown:incomplete-link)");
-
-    }
-
-
-
-    text.to_string()
-
-}
-
-
-
-/// Completes incomplete bold formatting (**).
-
-fn handle_incomplete_bold(text: &str) -> String {
-
-    if has_complete_code_block(text) {
-
-        return text.to_string();
-
-    }
-
-
-
-    if let Some(captures) = BOLD_PATTERN.captures(text) {
-
-        let content_after_marker = captures.get(2).unwrap().as_str();
-
-        if content_after_marker.is_empty()
-
-            || MEANINGLESS_CONTENT_PATTERN.is_match(content_after_marker)
-
-        {
-
-            return text.to_string();
-
-        }
-
-
-
-        let asterisk_pairs = text.matches("**").count();
-
-        if asterisk_pairs % 2 == 1 {
-
-            return format!("{text}**");
-
-        }
-
-    }
-
-
-
-    text.to_string()
-
+/// Options for [`process_markdown_and_bibtex_with_options`]. The default matches
+/// [`process_markdown_and_bibtex`].
+#[derive(Debug, Clone)]
+pub struct ProcessingOptions {
+    /// Heading of the bibliography section. When `None`, the heading is taken
+    /// from the front matter's `reference-section-title`, then from the style's
+    /// or the locale's `bibliography`/`references` term.
+    pub bibliography_title: Option<String>,
+    /// Headings of this level or above (1 for `#`) start a new reference
+    /// section with its own bibliography, e.g. one per chapter.
+    pub bibliography_scope_level: Option<usize>,
+    /// Entries to list without citing them, like `\nocite`: BibTeX or Markdown
+    /// keys, `*` for all entries, or filters such as `keyword=primary`. When
+    /// empty, the front matter's `nocite`.
+    pub nocite: Vec<String>,
+    /// Gives each citation an id (`cite-1`, `cite-2`, ...) and lists, after each
+    /// bibliography entry, links back to the citations of it, like biblatex's `backref`.
+    pub backlinks: bool,
+    /// Format of the bibliography input; detected from its content when `None`.
+    /// [`BibliographySource`]s carry their own format instead.
+    pub bibliography_format: Option<BibliographyFormat>,
+    /// Which entry is kept when several bibliographies define the same key.
+    pub duplicate_precedence: DuplicatePrecedence,
+    /// Locale code, such as `de-DE`, used instead of the style's default
+    /// locale (when `None`, the front matter's `lang`). Locales missing from
+    /// the locale file come from hayagriva's built-in CSL locales.
+    pub lang: Option<String>,
+    /// Whether in-text citations link to their bibliography entry. When
+    /// `false` they are rendered as plain `[Key]` text; when `None`, the
+    /// front matter's `link-citations` decides, and they are linked without one.
+    pub link_citations: Option<bool>,
+    /// Reads the options left unset above from the document's YAML front
+    /// matter. A front matter that is not valid YAML is then an error.
+    pub read_front_matter: bool,
+    /// Removes the YAML front matter from the output instead of copying it.
+    pub strip_front_matter: bool,
+    /// Also serialises the entries listed in the bibliographies, as CSL-JSON,
+    /// CSL-YAML, BibTeX, RIS or Hayagriva YAML, into
+    /// [`ProcessingOutput::exported_bibliography`]. EndNote XML is read only.
+    pub export_format: Option<BibliographyFormat>,
+    /// How the Markdown is completed before citations are replaced, for
+    /// documents cut off mid-stream. Partial tokens (a bare `@Coh`, a final
+    /// `$x`, a table row) are only completed with `trailing_block_only`;
+    /// [`RepairRules::NONE`] passes complete documents through untouched.
+    pub repair: RepairOptions,
 }
 
-
-
-/// Completes incomplete italic formatting with double underscores (__).
-
-fn handle_incomplete_double_underscore_italic(text: &str) -> String {
-
-    if let Some(captures) = ITALIC_PATTERN.captures(text) {
-
-        let content_after_marker = captures.get(2).unwrap().as_str();
-
-        if content_after_marker.is_empty()
-
-            || MEANINGLESS_CONTENT_PATTERN.is_match(content_after_marker)
-
-        {
-
-            return text.to_string();
-
-        }
-
-
-
-        let underscore_pairs = text.matches("__").count();
-
-        if underscore_pairs % 2 == 1 {
-
-            return format!("{text}__");
-
-        }
-
-    }
-
-
-
-    text.to_string()
-
-}
-
-
-
-/// Counts single asterisks that are not part of double asterisks or list markers.
-
-fn count_single_asterisks(text: &str) -> usize {
-
-    let chars: Vec<char> = text.chars().collect();
-
-    let mut count = 0;
-
-    for i in 0..chars.len() {
-
-        if chars[i] == '*' {
-
-            let prev_char = chars.get(i.wrapping_sub(1));
-
-            let next_char = chars.get(i + 1);
-
-
-
-            // Skip if part of ** or *** etc.
-
-            if (prev_char.map_or(false, |&c| c == '*'))
-
-                || (next_char.map_or(false, |&c| c == '*'))
-
-            {
-
-                continue;
-
-            }
-
-
-
-            // Skip if it's a list marker.
-
-            let line_start = text[..i].rfind('\n').map_or(0, |pos| pos + 1);
-
-            let before_asterisk = &text[line_start..i];
-
-            if before_asterisk.trim().is_empty() && next_char.map_or(false, |&c| c.is_whitespace())
-
-            {
-
-                continue;
-
-            }
-
-
-
-            count += 1;
-
-        }
-
-    }
-
-    count
-
-}
-
-
-
-/// Completes incomplete italic formatting with single asterisks (*).
-
-fn handle_incomplete_single_asterisk_italic(text: &str) -> String {
-
-    if has_complete_code_block(text) {
-
-        return text.to_string();
-
-    }
-
-
-
-    if let Some(captures) = SINGLE_ASTERISK_PATTERN.captures(text) {
-
-        let content_after_marker = captures.get(2).unwrap().as_str();
-
-        if content_after_marker.is_empty()
-
-            || MEANINGLESS_CONTENT_PATTERN.is_match(content_after_marker)
-
-        {
-
-            return text.to_string();
-
-        }
-
-
-
-        if count_single_asterisks(text) % 2 == 1 {
-
-            return format!("{text}*");
-
-        }
-
-    }
-
-
-
-    text.to_string()
-
-}
-
-
-
-/// Checks if a character position is within a math block ($ or $$).
-
-fn is_within_math_block(text: &str, position: usize) -> bool {
-
-    let mut in_inline_math = false;
-
-    let mut in_block_math = false;
-
-    let mut chars = text.chars().enumerate().peekable();
-
-
-
-    while let Some((i, ch)) = chars.next() {
-
-        if i >= position {
-
-            break;
-
-        }
-
-        if ch == '\\' && chars.peek().map_or(false, |&(_, next_ch)| next_ch == '$') {
-
-            chars.next(); // Skip escaped dollar sign
-
-            continue;
-
-        }
-
-        if ch == '$' {
-
-            if chars.peek().map_or(false, |&(_, next_ch)| next_ch == '$') {
-
-                in_block_math = !in_block_math;
-
-                chars.next(); // Skip second dollar sign
-
-                in_inline_math = false;
-
-            } else if !in_block_math {
-
-                in_inline_math = !in_inline_math;
-
-            }
-
+impl Default for ProcessingOptions {
+    fn default() -> Self {
+        Self {
+            bibliography_title: None,
+            bibliography_scope_level: None,
+            nocite: Vec::new(),
+            backlinks: false,
+            bibliography_format: None,
+            duplicate_precedence: DuplicatePrecedence::default(),
+            lang: None,
+            link_citations: None,
+            read_front_matter: true,
+            strip_front_matter: false,
+            export_format: None,
+            repair: RepairOptions::default(),
         }
-
     }
-
-
-
-    in_inline_math || in_block_math
-
-}
-
-
-
-
-
-/// Counts single underscores not part of double underscores or inside math blocks.
-
-fn count_single_underscores(text: &str) -> usize {
-
-    text.char_indices()
-
-        .filter(|&(i, ch)| {
-
-            if ch == '_' {
-
-                // Not part of __
-
-                let prev_char = text.chars().nth(i.saturating_sub(1));
-
-                let next_char = text.chars().nth(i + 1);
-
-                if prev_char == Some('_') || next_char == Some('_') {
-
-                    return false;
-
-                }
-
-
-
-                // Not escaped
-
-                if prev_char == Some('\\') {
-
-                    return false;
-
-                }
-
-
-
-                // Not inside math block
-
-                if is_within_math_block(text, i) {
-
-                    return false;
-
-                }
-
-
-
-                // Not word-internal
-
-                if let (Some(p), Some(n)) = (prev_char, next_char) {
-
-                    if (p.is_alphanumeric() || p == '_') && (n.is_alphanumeric() || n == '_') {
-
-                        return false;
-
-                    }
-
-                }
-
-
-
-                return true;
-
-            }
-
-            false
-
-        })
-
-        .count()
-
 }
 
-
-
-/// Completes incomplete italic formatting with single underscores (_).
-
-fn handle_incomplete_single_underscore_italic(text: &str) -> String {
-
-    if has_complete_code_block(text) {
-
-        return text.to_string();
-
-    }
-
-
-
-    if let Some(captures) = SINGLE_UNDERSCORE_PATTERN.captures(text) {
-
-        let content_after_marker = captures.get(2).unwrap().as_str();
-
-        if content_after_marker.is_empty()
-
-            || MEANINGLESS_CONTENT_PATTERN.is_match(content_after_marker)
-
-        {
-
-            return text.to_string();
-
+impl ProcessingOptions {
+    /// These options, with the ones left unset taken from `front_matter`:
+    /// explicit options, such as command-line flags, win over the document.
+    pub fn with_front_matter(&self, front_matter: &FrontMatter) -> Self {
+        let nocite = if self.nocite.is_empty() { &front_matter.nocite } else { &self.nocite };
+        Self {
+            bibliography_title: self
+                .bibliography_title
+                .clone()
+                .or_else(|| front_matter.reference_section_title.clone()),
+            nocite: nocite.clone(),
+            lang: self.lang.clone().or_else(|| front_matter.lang.clone()),
+            link_citations: self.link_citations.or(front_matter.link_citations),
+            ..self.clone()
         }
-
-
-
-        if count_single_underscores(text) % 2 == 1 {
-
-            return format!("{text}_");
-
-        }
-
     }
-
-
-
-    text.to_string()
-
-}
-
-
-
-/// Checks if a backtick is part of a triple-backtick sequence.
-
-fn is_part_of_triple_backtick(text: &str, i: usize) -> bool {
-
-    (text.len() >= i + 3 && &text[i..i + 3] == "```")
-
-        || (i > 0 && text.len() >= i + 2 && &text[i - 1..i + 2] == "```")
-
-        || (i > 1 && &text[i - 2..i + 1] == "```")
-
-}
-
-
-
-/// Counts single backticks that are not part of triple backticks.
-
-fn count_single_backticks(text: &str) -> usize {
-
-    text.char_indices()
-
-        .filter(|&(i, c)| c == '`' && !is_part_of_triple_backtick(text, i))
-
-        .count()
-
 }
 
+use once_cell::sync::Lazy;
 
+// Stands in for a bibliography inside the document until the citations are replaced.
+static BLOCK_PLACEHOLDER_PATTERN: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"<!-- bibliography-block-(\d+) -->").unwrap());
 
-/// Completes incomplete inline code formatting (`).
-
-fn handle_incomplete_inline_code(text: &str) -> String {
-
-    let all_triple_backticks = text.matches("```").count();
-
-    let inside_incomplete_code_block = all_triple_backticks % 2 == 1;
-
-
-
-    if all_triple_backticks > 0 && all_triple_backticks % 2 == 0 && text.contains('\n') {
-
-        return text.to_string();
-
-    }
-
-
-
-    if let Some(_captures) = INLINE_CODE_PATTERN.captures(text) {
-
-        if !inside_incomplete_code_block && count_single_backticks(text) % 2 == 1 {
-
-            return format!("{text}`");
-
-        }
-
-    }
-
-
-
-    text.to_string()
+// Numeric footnote references and definitions already present in the document.
+static NUMERIC_FOOTNOTE_PATTERN: Lazy<Regex> = Lazy::new(|| Regex::new(r"\[\^(\d+)\]").unwrap());
+// A bracketed span on one line that may hold a group of citations.
+static CITATION_GROUP_PATTERN: Lazy<Regex> = Lazy::new(|| Regex::new(r"\[[^\[\]\n]*\]").unwrap());
 
+pub fn process_markdown_and_bibtex(
+    markdown_input: &str,
+    bibtex_input: &str,
+    bibliography_link_prefix: &str,
+    csl_style: &str,
+    locale: &str,
+) -> Result<ProcessingOutput, String> {
+    process_markdown_and_bibtex_with_options(
+        markdown_input,
+        bibtex_input,
+        bibliography_link_prefix,
+        csl_style,
+        locale,
+        &ProcessingOptions::default(),
+    )
 }
 
-
-
-/// Completes incomplete strikethrough formatting (~~).
-
-fn handle_incomplete_strikethrough(text: &str) -> String {
-
-    if let Some(captures) = STRIKETHROUGH_PATTERN.captures(text) {
-
-        let content_after_marker = captures.get(2).unwrap().as_str();
-
-        if content_after_marker.is_empty()
-
-            || MEANINGLESS_CONTENT_PATTERN.is_match(content_after_marker)
-
-        {
-
-            return text.to_string();
-
-        }
-
-
-
-        let tilde_pairs = text.matches("~~").count();
-
-        if tilde_pairs % 2 == 1 {
-
-            return format!("{text}~~");
-
-        }
-
-    }
-
-
-
-    text.to_string()
-
+/// Same as [`process_markdown_and_bibtex`], with rendering controlled by `options`.
+pub fn process_markdown_and_bibtex_with_options(
+    markdown_input: &str,
+    bibtex_input: &str,
+    bibliography_link_prefix: &str,
+    csl_style: &str,
+    locale: &str,
+    options: &ProcessingOptions,
+) -> Result<ProcessingOutput, String> {
+    let source = BibliographySource {
+        name: "bibliography".to_string(),
+        content: bibtex_input.to_string(),
+        format: options.bibliography_format,
+    };
+    process_markdown_with_bibliographies(
+        markdown_input,
+        &[source],
+        bibliography_link_prefix,
+        csl_style,
+        locale,
+        options,
+    )
 }
 
+/// Same as [`process_markdown_and_bibtex_with_options`], merging several
+/// bibliographies according to `options.duplicate_precedence`. Bibliographies
+/// embedded in the document (a front-matter `references` list or ```` ```bibtex ````
+/// blocks) are merged too, and win over `bibliographies` whatever the precedence.
+pub fn process_markdown_with_bibliographies(
+    markdown_input: &str,
+    bibliographies: &[BibliographySource],
+    bibliography_link_prefix: &str,
+    csl_style: &str,
+    locale: &str,
+    options: &ProcessingOptions,
+) -> Result<ProcessingOutput, String> {
+    let front_matter = if options.read_front_matter {
+        FrontMatter::parse(markdown_input)?
+    } else {
+        FrontMatter::default()
+    };
+    let options = &options.with_front_matter(&front_matter);
+
+    // Bibliographies embedded in the document come first, and win over
+    // external ones on duplicate keys whatever the precedence.
+    let (markdown_without_bibliographies, embedded_bibliographies) =
+        embedded::extract(markdown_input);
+    // The front matter is copied as-is (or dropped), never processed.
+    let (front_matter_block, markdown_input) = match front_matter::find(&markdown_without_bibliographies) {
+        Some((block, _)) => (
+            &markdown_without_bibliographies[block.clone()],
+            &markdown_without_bibliographies[block.end..],
+        ),
+        None => ("", markdown_without_bibliographies.as_str()),
+    };
+    // The repair sees the citations as written, so that a partial `[@Cohe`
+    // is held back rather than linked, and never the links made of them.
+    // A complete document may well end with `@handle`, `$5` or a table row;
+    // only a streamed tail, marked by `trailing_block_only`, is taken to end
+    // mid-token, and there a bare `@Coh` is a citation being written.
+    let streamed_tail = options.repair.trailing_block_only;
+    let repair_options = RepairOptions {
+        bare_citation_keys: streamed_tail,
+        partial_tokens: streamed_tail && options.repair.partial_tokens,
+        ..options.repair.clone()
+    };
+    let repaired_markdown = repair_markdown(markdown_input, &repair_options);
+    let markdown_input = repaired_markdown.as_str();
 
+    // --- 1. Define Regex & Find Unique Citations ---
+    let unique_citations = find_unique_citations(markdown_input);
 
-/// Completes incomplete block KaTeX formatting ($$).
+    // --- 2. Parse BibTeX (or another supported format) using Hayagriva ---
+    // The source records are kept for bibliography filters on types and fields.
+    let (loaded_bibliography, merge_conflicts) =
+        load_bibliographies(&embedded_bibliographies, bibliographies, options.duplicate_precedence)?;
+    let source_records = &loaded_bibliography.records;
 
-fn handle_incomplete_block_katex(text: &str) -> String {
+    // --- 3. Group BibTeX entries by (first_author_lastname_lc, year_yy) & Sort by Title ---
+    let grouped_entries = group_entries(&loaded_bibliography.library);
 
-    let dollar_pairs = text.matches("$$").count();
+    // --- 4. Map Markdown keys to specific BibTeX entries (Exact & Fuzzy Matching) ---
+    let final_entry_map = map_citations_to_entries(&unique_citations, &grouped_entries);
 
-    if dollar_pairs % 2 == 1 {
+    // --- 5. Generate Bibliography (Deduplicated and Sorted) ---
+    let style = IndependentStyle::from_xml(&strip_unsupported_terms(csl_style))
+        .map_err(|e| format!("CSL parsing error: {}", e))?;
+    // An empty locale leaves only the built-in locales.
+    let locale_file = if locale.trim().is_empty() {
+        None
+    } else {
+        Some(
+            LocaleFile::from_xml(&strip_unsupported_terms(locale))
+                .map_err(|e| format!("Locale parsing error: {}", e))?,
+        )
+    };
+    // Without `lang`, the language is that of the locale file, if any.
+    let locale_code = options
+        .lang
+        .clone()
+        .map(LocaleCode)
+        .or_else(|| locale_file.as_ref().map(|file| file.lang.clone()));
+    let heading_locale = locale_code
+        .clone()
+        .or_else(|| style.default_locale.clone())
+        .unwrap_or_else(LocaleCode::en_us);
+    // The locale file's heading only applies if it is in the requested language.
+    let locale_heading_xml = match &locale_file {
+        Some(file) if same_language(&file.lang, &heading_locale) => locale,
+        _ => "",
+    };
+    let mut locales: Vec<Locale> = locale_file.into_iter().map(Locale::from).collect();
+    locales.extend(hayagriva::archive::locales());
+
+    let default_title = bibliography_title(options, csl_style, locale_heading_xml)
+        .or_else(|| builtin_heading(&locales, &heading_locale))
+        .unwrap_or_else(|| DEFAULT_BIBLIOGRAPHY_TITLE.to_string());
+
+    // Each reference section lists the entries cited in it, deduplicated by
+    // BibTeX key and keeping the first citation key (alphabetically) as anchor.
+    let layout = sections::scan(markdown_input, options.bibliography_scope_level);
+    let section_ranges: Vec<Range<usize>> = (0..layout.section_starts.len())
+        .map(|section| layout.section_range(section, markdown_input.len()))
+        .collect();
+
+    // Text of a section outside its markers and directives.
+    let section_pieces = |section: usize| -> Vec<&str> {
+        let range = &section_ranges[section];
+        let mut cursor = range.start;
+        let mut pieces = Vec::new();
+        for (cut, _) in layout.cuts(section) {
+            pieces.push(&markdown_input[cursor..cut.start]);
+            cursor = cut.end;
+        }
+        pieces.push(&markdown_input[cursor..range.end]);
+        pieces
+    };
 
-        if let Some(first_dollar_index) = text.find("$$") {
+    // Options-level `nocite` keys belong to the bibliography ending the document.
+    let mut nocites: Vec<&sections::Nocite> = layout.nocites.iter().collect();
+    let option_nocite = (!options.nocite.is_empty())
+        .then(|| sections::Nocite::parse(&options.nocite.join(" "), section_ranges.len() - 1, None));
+    nocites.extend(option_nocite.as_ref());
+
+    let section_items_to_render: Vec<Vec<(&Entry, String)>> = (0..section_ranges.len())
+        .map(|section| {
+            let cited_keys = section_pieces(section)
+                .into_iter()
+                .flat_map(|piece| CITATION_PATTERN.captures_iter(piece))
+                .map(|caps| citation_key(&caps));
+            let nocited = nocites
+                .iter()
+                .filter(|nocite| nocite.section == section)
+                .flat_map(|nocite| nocite_entries(nocite, &loaded_bibliography, &grouped_entries));
+            section_entries(cited_keys, nocited, &final_entry_map)
+        })
+        .collect();
+
+    // Resolved citations are numbered in document order; with back-references
+    // each entry lists the numbers of the citations pointing to it.
+    let mut citation_numbers: HashMap<(usize, String), Vec<usize>> = HashMap::new();
+    let mut citation_count = 0;
+    for section in 0..section_ranges.len() {
+        for piece in section_pieces(section) {
+            for caps in CITATION_PATTERN.captures_iter(piece) {
+                if let Some(entry) = final_entry_map.get(&citation_key(&caps)) {
+                    citation_count += 1;
+                    citation_numbers
+                        .entry((section, entry.key().to_string()))
+                        .or_default()
+                        .push(citation_count);
+                }
+            }
+        }
+    }
 
-            let has_newline_after_start = text[first_dollar_index..].contains('\n');
+    let mut renderer = BibliographyRenderer {
+        style: &style,
+        locale: locale_code.clone(),
+        locales: &locales,
+        default_title: &default_title,
+        section_count: section_ranges.len(),
+        citation_numbers: options.backlinks.then_some(&citation_numbers),
+        anchored_entries: HashSet::new(),
+    };
+    let rendered_blocks: Vec<String> = layout
+        .blocks
+        .iter()
+        .map(|block| {
+            let items: Vec<&(&Entry, String)> = section_items_to_render[block.section]
+                .iter()
+                .filter(|(entry, _)| block.filter.matches(entry, source_records.get(entry.key())))
+                .collect();
+            // Sections without citations only get a bibliography when asked for one.
+            if items.is_empty() && block.marker.is_none() && !layout.is_single() {
+                String::new()
+            } else {
+                renderer.render(block, &items)
+            }
+        })
+        .collect();
 
-            if has_newline_after_start && !text.ends_with('\n') {
+    // --- 6. Replace citations in Markdown ---
+    // Note styles cite in footnotes, numbered after any the document already has.
+    let mut counter = CitationCounter { count: 0, backlinks: options.backlinks };
+    let mut notes = (style.settings.class == StyleClass::Note).then(|| NoteCitations {
+        first_note: NUMERIC_FOOTNOTE_PATTERN
+            .captures_iter(markdown_input)
+            .filter_map(|caps| caps[1].parse::<usize>().ok())
+            .max()
+            .unwrap_or(0)
+            + 1,
+        notes: Vec::new(),
+    });
+    let in_text = InTextCitations {
+        section_items: &section_items_to_render,
+        link_prefix: bibliography_link_prefix,
+        link_citations: options.link_citations != Some(false),
+    };
+    let mut replace_citations = |text: &str, section: usize| -> String {
+        match notes.as_mut() {
+            Some(notes) => notes.replace(text, &final_entry_map, &mut counter),
+            None => in_text.replace(text, section, &final_entry_map, &mut counter),
+        }
+    };
 
-                return format!("{text}\n$$");
+    // The export lists every rendered entry once, in order of first appearance.
+    let exported_bibliography = match options.export_format {
+        Some(format) => {
+            let mut seen = HashSet::new();
+            let entries: Vec<&Entry> = section_items_to_render
+                .iter()
+                .flatten()
+                .map(|(entry, _)| *entry)
+                .filter(|entry| seen.insert(entry.key()))
+                .collect();
+            Some(export::write(&entries, format)?)
+        }
+        None => None,
+    };
 
+    // Bibliographies inside the document are marked with placeholders; the
+    // one ending the document is returned separately.
+    let trailing_block = layout
+        .blocks
+        .last()
+        .filter(|block| block.marker.is_none())
+        .map(|_| layout.blocks.len() - 1);
+    let mut prepared_markdown = String::new();
+    for (section, range) in section_ranges.iter().enumerate() {
+        let mut cursor = range.start;
+        for (cut, kind) in layout.cuts(section) {
+            prepared_markdown.push_str(&replace_citations(&markdown_input[cursor..cut.start], section));
+            cursor = cut.end;
+            if let sections::Cut::Block(index) = kind {
+                if !rendered_blocks[index].is_empty() {
+                    prepared_markdown.push_str(&format!("<!-- bibliography-block-{} -->", index));
+                }
             }
+        }
+        prepared_markdown.push_str(&replace_citations(&markdown_input[cursor..range.end], section));
 
+        if let Some(index) = layout.appended_block(section) {
+            if Some(index) == trailing_block || rendered_blocks[index].is_empty() {
+                continue;
+            }
+            if !prepared_markdown.is_empty() && !prepared_markdown.ends_with("\n\n") {
+                prepared_markdown.push_str(if prepared_markdown.ends_with('\n') { "\n" } else { "\n\n" });
+            }
+            prepared_markdown.push_str(&format!("<!-- bibliography-block-{} -->\n\n", index));
         }
+    }
 
-        return format!("{text}$$");
+    let modified_markdown_content = BLOCK_PLACEHOLDER_PATTERN
+        .replace_all(&prepared_markdown, |caps: &Captures| {
+            caps[1]
+                .parse::<usize>()
+                .ok()
+                .and_then(|index| rendered_blocks.get(index))
+                .map_or_else(|| caps[0].to_string(), |block| block.clone())
+        })
+        .into_owned();
 
-    }
+    let note_definitions = match &notes {
+        Some(notes) => notes.definitions(&style, locale_code, &locales),
+        None => Vec::new(),
+    };
+    let modified_markdown_content = if note_definitions.is_empty() {
+        modified_markdown_content
+    } else {
+        format!("{}\n\n{}", modified_markdown_content.trim_end(), note_definitions.join("\n"))
+    };
+    let modified_markdown_content = if options.strip_front_matter {
+        modified_markdown_content.trim_start_matches(['\r', '\n']).to_string()
+    } else {
+        format!("{}{}", front_matter_block, modified_markdown_content)
+    };
 
-    text.to_string()
+    let bibliography_content = trailing_block
+        .map(|index| rendered_blocks[index].clone())
+        .unwrap_or_default();
 
+    Ok(ProcessingOutput {
+        modified_markdown: modified_markdown_content,
+        bibliography_markdown: bibliography_content,
+        unmapped_fields: loaded_bibliography.unmapped,
+        merge_conflicts,
+        exported_bibliography,
+    })
 }
 
+/// The entries a section's bibliographies list: those cited in it, anchored
+/// by their first Markdown key alphabetically, then the `nocite` ones, each
+/// entry once, sorted by author, year and title.
+fn section_entries<'a>(
+    cited_keys: impl Iterator<Item = String>,
+    nocited: impl Iterator<Item = (&'a Entry, String)>,
+    final_entry_map: &HashMap<String, &'a Entry>,
+) -> Vec<(&'a Entry, String)> {
+    // Sort keys to ensure deterministic order
+    let mut sorted_md_keys: Vec<String> =
+        cited_keys.filter(|md_key| final_entry_map.contains_key(md_key)).collect();
+    sorted_md_keys.sort();
+    sorted_md_keys.dedup();
 
+    let cited = sorted_md_keys.into_iter().map(|md_key| (final_entry_map[&md_key], md_key));
+    let mut used_bib_keys: HashSet<String> = HashSet::new();
+    let mut items: Vec<(&Entry, String)> = cited
+        .chain(nocited)
+        .filter(|(entry, _)| used_bib_keys.insert(entry.key().to_string()))
+        .collect();
 
-/// Completes incomplete bold-italic formatting (***).
-
-fn handle_incomplete_bold_italic(text: &str) -> String {
+    items.sort_by(|a, b| {
+        let author_a = get_authors_string(a.0);
+        let author_b = get_authors_string(b.0);
+        let year_a = entry_date(a.0).map(|d| d.year.to_string()).unwrap_or_default();
+        let year_b = entry_date(b.0).map(|d| d.year.to_string()).unwrap_or_default();
 
-    if has_complete_code_block(text) {
+        author_a
+            .cmp(&author_b)
+            .then_with(|| year_a.cmp(&year_b))
+            .then_with(|| get_entry_title_for_sort(a.0).cmp(&get_entry_title_for_sort(b.0)))
+    });
+    items
+}
 
-        return text.to_string();
+/// The entries a `nocite` directive or option lists, each with the key it
+/// is anchored by: the key it was listed with, or its BibTeX key when
+/// selected by a filter.
+fn nocite_entries<'a>(
+    nocite: &sections::Nocite,
+    loaded: &'a import::LoadedBibliography,
+    grouped_entries: &HashMap<EntryGroupKey, Vec<&'a Entry>>,
+) -> Vec<(&'a Entry, String)> {
+    let listed = nocite
+        .keys
+        .iter()
+        .filter_map(|key| resolve_nocite_key(key, &loaded.library, grouped_entries));
+    let selected = nocite.filter.iter().flat_map(|filter| {
+        loaded
+            .library
+            .iter()
+            .filter(|entry| filter.matches(entry, loaded.records.get(entry.key())))
+            .map(|entry| (entry, entry.key().to_string()))
+    });
+    listed.chain(selected).collect()
+}
 
+/// Resolves a `nocite` key, a BibTeX key or a Markdown key without the `@`.
+fn resolve_nocite_key<'a>(
+    key: &str,
+    library: &'a hayagriva::Library,
+    grouped_entries: &HashMap<EntryGroupKey, Vec<&'a Entry>>,
+) -> Option<(&'a Entry, String)> {
+    if let Some(entry) = library.get(key) {
+        return Some((entry, key.to_string()));
     }
+    let at_key = format!("@{}", key);
+    let caps = CITATION_PATTERN.captures(&at_key).filter(|caps| caps[0].len() == at_key.len())?;
+    let suffix_part = caps.get(4).map_or("", |m| m.as_str());
+    let suffix_part = if suffix_part.is_empty() { "a" } else { suffix_part };
+    resolve_short_key(&caps[2], &caps[3], suffix_part, grouped_entries)
+        .map(|entry| (entry, citation_key(&caps)))
+}
 
-    // This check prevents cases like **** from being treated as incomplete ***
-
-    if text.starts_with("****") {
-
-        return text.to_string();
-
+/// Anchor of an entry's bibliography item. It only needs a section suffix
+/// once the same key can appear in several lists.
+fn entry_anchor(md_key: &str, section: usize, section_count: usize) -> String {
+    if section_count == 1 {
+        md_key.to_string()
+    } else {
+        format!("{}-{}", md_key, section + 1)
     }
+}
 
+/// Renders the bibliographies of a document, sharing the style and the
+/// anchors given out so far.
+struct BibliographyRenderer<'a> {
+    style: &'a IndependentStyle,
+    locale: Option<LocaleCode>,
+    locales: &'a [Locale],
+    default_title: &'a str,
+    section_count: usize,
+    /// Numbers of the citations of each (section, BibTeX key), when each
+    /// entry links back to them.
+    citation_numbers: Option<&'a HashMap<(usize, String), Vec<usize>>>,
+    /// Entries already given an `id`, by section.
+    anchored_entries: HashSet<(usize, String)>,
+}
 
-
-    if let Some(captures) = BOLD_ITALIC_PATTERN.captures(text) {
-
-        let content_after_marker = captures.get(2).unwrap().as_str();
-
-        if content_after_marker.is_empty()
-
-            || MEANINGLESS_CONTENT_PATTERN.is_match(content_after_marker)
-
-        {
-
-            return text.to_string();
-
+impl BibliographyRenderer<'_> {
+    /// Renders one bibliography: its heading and an item per entry.
+    fn render(&mut self, block: &sections::Block, items: &[&(&Entry, String)]) -> String {
+        let bibliography_heading =
+            format!("### {}", block.title.as_deref().unwrap_or(self.default_title));
+        if items.is_empty() {
+            return bibliography_heading;
         }
 
-
-
-        let triple_asterisk_count = text.matches("***").count();
-
-        if triple_asterisk_count % 2 == 1 {
-
-            return format!("{text}***");
-
+        let mut bibliography_markdown_lines: Vec<String> = vec![bibliography_heading, String::new()];
+        let entries: Vec<&Entry> = items.iter().map(|(entry, _)| *entry).collect();
+        let formatted_entries =
+            format_bib_entries_for_markdown(&entries, self.style, self.locale.clone(), self.locales);
+        for ((entry, md_key), formatted_entry) in items.iter().zip(formatted_entries) {
+            let anchor = entry_anchor(md_key, block.section, self.section_count);
+            // An entry listed by several filtered bibliographies is only a link target once.
+            let entry_key = (block.section, entry.key().to_string());
+            let is_target = self.anchored_entries.insert(entry_key.clone());
+            let id = if is_target { format!(" id=\"{}\"", anchor) } else { String::new() };
+            let backrefs = match self.citation_numbers.and_then(|numbers| numbers.get(&entry_key)) {
+                Some(numbers) if is_target => backlinks(numbers),
+                _ => String::new(),
+            };
+            bibliography_markdown_lines.push(format!(
+                "#### {}<a href=\"#{}\"{}></a>{}",
+                formatted_entry, anchor, id, backrefs
+            ));
         }
-
+        bibliography_markdown_lines.join("\n")
     }
-
-
-
-    text.to_string()
-
 }
 
+/// The ↩ list of links back to the citations numbered `numbers`.
+fn backlinks(numbers: &[usize]) -> String {
+    let links: Vec<String> = numbers
+        .iter()
+        .map(|n| format!("<a href=\"#cite-{}\">{}</a>", n, n))
+        .collect();
+    format!(" \u{21a9} {}", links.join(", "))
+}
 
+/// Numbers resolved citations in document order and, with back-references,
+/// gives each an anchor (`cite-1`, `cite-2`, ...).
+struct CitationCounter {
+    count: usize,
+    backlinks: bool,
+}
 
-/// Parses markdown text and completes incomplete tokens to prevent partial rendering.
-
-pub fn parse_incomplete_markdown(text: &str) -> String {
-
-    if text.is_empty() {
-
-        return text.to_string();
-
+impl CitationCounter {
+    /// Counts one citation, returning its anchor, or nothing without back-references.
+    fn next_anchor(&mut self) -> String {
+        self.count += 1;
+        if self.backlinks {
+            format!("<a id=\"cite-{}\"></a>", self.count)
+        } else {
+            String::new()
+        }
     }
+}
 
+/// Citations rendered in the text, linking to the bibliography of their
+/// section (or as plain `[Key]` text).
+struct InTextCitations<'a> {
+    section_items: &'a [Vec<(&'a Entry, String)>],
+    link_prefix: &'a str,
+    link_citations: bool,
+}
 
-
-    // Handle incomplete links and images first.
-
-    let mut result = handle_incomplete_links_and_images(text);
-
-
-
-    // If a special incomplete link marker was added, don't process other formatting.
-
-    if result.ends_with("](streamdown:incomplete-link)") {
-
-        return result;
-
+impl InTextCitations<'_> {
+    fn replace(
+        &self,
+        text: &str,
+        section: usize,
+        final_entry_map: &HashMap<String, &Entry>,
+        counter: &mut CitationCounter,
+    ) -> String {
+        CITATION_PATTERN
+            .replace_all(text, |caps: &Captures| {
+                let md_key = citation_key(caps);
+                let Some(entry) = final_entry_map.get(&md_key) else {
+                    return format!("@{}", md_key);
+                };
+                let backlink_target = counter.next_anchor();
+                let listed = self.section_items[section].iter().find(|(item, _)| item.key() == entry.key());
+                match listed {
+                    Some((_, anch)) if !self.link_citations => format!("{}[{}]", backlink_target, anch),
+                    Some((_, anch)) => format!(
+                        "{}[[{}]]({}#{})",
+                        backlink_target,
+                        anch,
+                        self.link_prefix,
+                        entry_anchor(anch, section, self.section_items.len())
+                    ),
+                    None => format!("@{}", md_key),
+                }
+            })
+            .into_owned()
     }
+}
 
+/// Citations rendered as footnotes, for note styles: a bracketed group of
+/// resolved keys, e.g. `[@Cohe63; @Swet15]`, is one note replacing the
+/// brackets, and any other key a note of its own.
+struct NoteCitations<'a> {
+    /// Number of the first note, after any the document already has.
+    first_note: usize,
+    /// The entries cited by each note, in document order.
+    notes: Vec<Vec<&'a Entry>>,
+}
 
+impl<'a> NoteCitations<'a> {
+    fn replace(
+        &mut self,
+        text: &str,
+        final_entry_map: &HashMap<String, &'a Entry>,
+        counter: &mut CitationCounter,
+    ) -> String {
+        let groups = note_groups(text, final_entry_map);
+        let mut replaced = String::with_capacity(text.len());
+        let mut cursor = 0;
+        for caps in CITATION_PATTERN.captures_iter(text) {
+            let key = caps.get(0).unwrap();
+            if key.start() < cursor {
+                continue;
+            }
+            if let Some((group, entries)) = groups.iter().find(|(group, _)| group.contains(&key.start())) {
+                replaced.push_str(&text[cursor..group.start]);
+                for _ in entries {
+                    replaced.push_str(&counter.next_anchor());
+                }
+                replaced.push_str(&self.add_note(entries.clone()));
+                cursor = group.end;
+                continue;
+            }
+            replaced.push_str(&text[cursor..key.start()]);
+            cursor = key.end();
+
+            let md_key = citation_key(&caps);
+            match final_entry_map.get(&md_key) {
+                Some(&entry) => {
+                    replaced.push_str(&counter.next_anchor());
+                    replaced.push_str(&self.add_note(vec![entry]));
+                }
+                None => replaced.push_str(&format!("@{}", md_key)),
+            }
+        }
+        replaced.push_str(&text[cursor..]);
+        replaced
+    }
 
-    // The order of operations is important to handle nested and combined formatting.
-
-
-    
-
-
-    result = handle_incomplete_inline_code(&result);
-
-    result = handle_incomplete_bold_italic(&result);
-
-    result = handle_incomplete_bold(&result);
-
-    result = handle_incomplete_double_underscore_italic(&result);
-
-    result = handle_incomplete_single_asterisk_italic(&result);
-
-    result = handle_incomplete_single_underscore_italic(&result);
-
-    result = handle_incomplete_strikethrough(&result);
+    /// Adds a note citing `entries`, returning its footnote reference.
+    fn add_note(&mut self, entries: Vec<&'a Entry>) -> String {
+        self.notes.push(entries);
+        format!("[^{}]", self.first_note + self.notes.len() - 1)
+    }
 
-    result = handle_incomplete_block_katex(&result);
+    /// The footnote definitions, `[^N]: ...`, of the notes added so far.
+    fn definitions(&self, style: &IndependentStyle, locale: Option<LocaleCode>, locales: &[Locale]) -> Vec<String> {
+        format_notes_for_markdown(&self.notes, self.first_note, style, locale, locales)
+            .into_iter()
+            .enumerate()
+            .map(|(i, note)| format!("[^{}]: {}", self.first_note + i, note))
+            .collect()
+    }
+}
 
+/// The bracketed groups of `text` holding nothing but resolved citations,
+/// with their entries; a group followed by `(`, `[` or `:` is a link or a
+/// reference definition.
+fn note_groups<'a>(
+    text: &str,
+    final_entry_map: &HashMap<String, &'a Entry>,
+) -> Vec<(Range<usize>, Vec<&'a Entry>)> {
+    CITATION_GROUP_PATTERN
+        .find_iter(text)
+        .filter(|group| !text[group.end()..].starts_with(['(', '[', ':']))
+        .filter(|group| {
+            CITATION_PATTERN
+                .replace_all(group.as_str(), "")
+                .trim_matches(|c: char| c == '[' || c == ']' || c == ';' || c == ',' || c.is_whitespace())
+                .is_empty()
+        })
+        .filter_map(|group| {
+            let entries: Option<Vec<&Entry>> = CITATION_PATTERN
+                .captures_iter(group.as_str())
+                .map(|caps| final_entry_map.get(&citation_key(&caps)).copied())
+                .collect();
+            entries.filter(|entries| !entries.is_empty()).map(|entries| (group.range(), entries))
+        })
+        .collect()
+}
 
+/// Writes a bibliography with only the entries cited in `markdown_input` (the
+/// entries of `final_entry_map`), in their original order and, for BibTeX, with
+/// their original text, `@string` and `@xdata` definitions and the `crossref`
+/// parents they inherit from, however deep.
+///
+/// Entries from other formats are converted, as by
+/// [`ProcessingOptions::export_format`].
+pub fn extract_cited_bibliography(
+    markdown_input: &str,
+    bibliographies: &[BibliographySource],
+    format: BibliographyFormat,
+    duplicate_precedence: DuplicatePrecedence,
+) -> Result<String, String> {
+    let (markdown_without_bibliographies, embedded_bibliographies) =
+        embedded::extract(markdown_input);
+    let markdown_input = match front_matter::find(&markdown_without_bibliographies) {
+        Some((block, _)) => &markdown_without_bibliographies[block.end..],
+        None => markdown_without_bibliographies.as_str(),
+    };
+    let unique_citations = find_unique_citations(markdown_input);
+    let (loaded_bibliography, _) =
+        load_bibliographies(&embedded_bibliographies, bibliographies, duplicate_precedence)?;
+    let grouped_entries = group_entries(&loaded_bibliography.library);
+    let final_entry_map = map_citations_to_entries(&unique_citations, &grouped_entries);
+
+    // Entries inheriting fields need their parents in the file too, and
+    // theirs in turn (e.g. inbook, book, mvbook).
+    let mut cited: HashSet<&str> = HashSet::new();
+    let mut pending: Vec<&str> = final_entry_map.values().map(|entry| entry.key()).collect();
+    while let Some(key) = pending.pop() {
+        if !cited.insert(key) {
+            continue;
+        }
+        let Some(record) = loaded_bibliography.records.get(key) else {
+            continue;
+        };
+        let parents = ["crossref", "xdata"]
+            .into_iter()
+            .filter_map(|field| record.fields.get(field))
+            .flat_map(|keys| keys.split(','))
+            .map(str::trim)
+            .filter(|key| !key.is_empty());
+        pending.extend(parents);
+    }
 
-    result
+    let entries: Vec<&Entry> = loaded_bibliography
+        .library
+        .iter()
+        .filter(|entry| cited.contains(entry.key()))
+        .collect();
+    let record = |entry: &Entry| loaded_bibliography.records.get(entry.key());
+
+    match format {
+        BibliographyFormat::BibTeX => {
+            let mut blocks = loaded_bibliography.bibtex_macros.clone();
+            for entry in &entries {
+                let text = record(entry).and_then(|record| record.bibtex.clone());
+                blocks.push(text.unwrap_or_else(|| export::bibtex_entry(entry)));
+            }
+            Ok(blocks.join("\n\n") + "\n")
+        }
+        BibliographyFormat::CslJson => {
+            let items: Vec<serde_json::Value> = entries
+                .iter()
+                .map(|entry| {
+                    let item = record(entry).and_then(|record| record.csl.clone());
+                    item.unwrap_or_else(|| export::csl_item(entry))
+                })
+                .collect();
+            serde_json::to_string_pretty(&items).map_err(|e| e.to_string())
+        }
+        _ => export::write(&entries, format),
+    }
+}
 
+/// Reads a bibliography in any supported format into hayagriva entries, along
+/// with the fields that could not be translated (only RIS and EndNote XML
+/// records lose fields). The format is detected from the content when `None`.
+pub fn import_bibliography(
+    input: &str,
+    format: Option<BibliographyFormat>,
+) -> Result<(hayagriva::Library, Vec<UnmappedField>), String> {
+    let loaded = import::load(input, format)?;
+    Ok((loaded.library, loaded.unmapped))
 }
 
+// --- Helper Functions ---
 
-pub fn process_markdown_and_bibtex(
-    markdown_input: &str,
-    bibtex_input: &str,
-    bibliography_link_prefix: &str,
-    csl_style: &str,
-    locale: &str,
-) -> Result<ProcessingOutput, String> {
-    // --- 1. Define Regex & Find Unique Citations ---
-    let citation_regex = Regex::new(r"(@([a-zA-Z]+)(\d{2})([a-z]?))\b")
-        .map_err(|e| format!("Regex compilation error: {}", e))?;
+static CITATION_PATTERN: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(@([a-zA-Z]+)(\d{2})([a-z]?))\b").unwrap());
 
+/// Finds the short citation keys of a document, mapping each Markdown key to
+/// its author part, year and suffix (`a` when none is written).
+fn find_unique_citations(markdown_input: &str) -> HashMap<String, (String, String, String)> {
     let mut unique_citations: HashMap<String, (String, String, String)> = HashMap::new();
-    for cap in citation_regex.captures_iter(markdown_input) {
+    for cap in CITATION_PATTERN.captures_iter(markdown_input) {
         let author_part = cap.get(2).map_or("", |m| m.as_str()).to_string();
         let year_part = cap.get(3).map_or("", |m| m.as_str()).to_string();
         let suffix_part = cap.get(4).map_or("", |m| m.as_str()).to_string();
@@ -734,18 +865,35 @@ pub fn process_markdown_and_bibtex(
         if !full_match.is_empty() {
             unique_citations
                 .entry(full_match)
-                .or_insert((author_part, year_part, if suffix_part==""{"a".to_string()} else {suffix_part}));
+                .or_insert((author_part, year_part, if suffix_part.is_empty(){"a".to_string()} else {suffix_part}));
         }
     }
+    unique_citations
+}
 
-    // --- 2. Parse BibTeX using Hayagriva ---
-    let bib_entries = from_biblatex_str(bibtex_input)
-        .map_err(|e| format!("BibTeX parsing error: {:?}", e))
-        .unwrap_or_default();
+/// Parses and merges the embedded bibliographies, then the external ones.
+fn load_bibliographies(
+    embedded_bibliographies: &[BibliographySource],
+    bibliographies: &[BibliographySource],
+    duplicate_precedence: DuplicatePrecedence,
+) -> Result<(import::LoadedBibliography, Vec<MergeConflict>), String> {
+    let loaded_bibliographies = embedded_bibliographies
+        .iter()
+        .chain(bibliographies)
+        .map(|source| {
+            let loaded = import::load(&source.content, source.format)
+                .map_err(|e| format!("Bibliography parsing error in {}: {}", source.name, e))?;
+            Ok((source.name.clone(), loaded))
+        })
+        .collect::<Result<_, String>>()?;
+    import::merge(loaded_bibliographies, embedded_bibliographies.len(), duplicate_precedence)
+}
 
-    // --- 3. Group BibTeX entries by (first_author_lastname_lc, year_yy) & Sort by Title ---
-    let mut grouped_entries: HashMap<(String, String), Vec<&Entry>> = HashMap::new();
-    for entry in &bib_entries {
+/// Groups entries by (first author last name lowercased, two-digit year),
+/// each group sorted by title.
+fn group_entries(bib_entries: &hayagriva::Library) -> HashMap<EntryGroupKey, Vec<&Entry>> {
+    let mut grouped_entries: HashMap<EntryGroupKey, Vec<&Entry>> = HashMap::new();
+    for entry in bib_entries {
         if let (Some(first_last_name_lc), Some(year_yy)) =
             (get_first_author_last_name(entry), get_year_yy(entry))
         {
@@ -756,152 +904,40 @@ pub fn process_markdown_and_bibtex(
         }
     }
     for group in grouped_entries.values_mut() {
-        group.sort_by(|a, b| get_entry_title_for_sort(a).cmp(&get_entry_title_for_sort(b)));
+        group.sort_by_key(|a| get_entry_title_for_sort(a));
     }
+    grouped_entries
+}
 
-    // --- 4. Map Markdown keys to specific BibTeX entries (Exact & Fuzzy Matching) ---
+/// Maps Markdown keys to specific entries (exact and fuzzy matching); keys
+/// without a match are left out.
+fn map_citations_to_entries<'a>(
+    unique_citations: &HashMap<String, (String, String, String)>,
+    grouped_entries: &HashMap<EntryGroupKey, Vec<&'a Entry>>,
+) -> HashMap<String, &'a Entry> {
     let mut final_entry_map: HashMap<String, &Entry> = HashMap::new(); // MD Key -> Bib Entry Ref
-    let mut missing_keys: HashSet<String> = unique_citations.keys().cloned().collect();
-
-    for (md_key, (author_part, year_part, suffix_part)) in &unique_citations {
-        let md_author_lc = author_part.to_lowercase();
-        let lookup_key = (md_author_lc.clone(), year_part.clone());
-        let mut found_match = false;
-
-        // --- 4a. Try Exact Match ---
-        if let Some(candidate_group) = grouped_entries.get(&lookup_key) {
-            let index = suffix_to_index(suffix_part);
-            if let Some(selected_entry) = candidate_group.get(index) {
-                final_entry_map.insert(author_part.clone()+year_part+if suffix_part=="a"{""} else {suffix_part}, selected_entry);
-                missing_keys.remove(md_key);
-                found_match = true;
-            }
-        }
-
-        // --- 4b. Try Fuzzy Match if Exact Failed ---
-        if !found_match {
-            const FUZZY_MATCH_THRESHOLD: usize = 2; // Stricter threshold
-            let mut best_fuzzy_match: Option<(usize, (&(String, String), &Vec<&Entry>))> = None;
-
-            for entry in &grouped_entries {
-                    if entry.0.1== *year_part {
-                            let distance = levenshtein(&md_author_lc, &entry.0.0);
-                            if distance <= FUZZY_MATCH_THRESHOLD
-                                && (best_fuzzy_match.is_none()
-                                    || distance < best_fuzzy_match.as_ref().unwrap().0)
-                            {
-                                best_fuzzy_match = Some((distance, entry));
-                            }
-                        }
-            }
-         
-            if let Some((_dist, selected_entry)) = best_fuzzy_match {
-                let index = suffix_to_index(suffix_part);
-                if let Some(selected_entry2) = selected_entry.1.get(index) {
-                  final_entry_map.insert(author_part.clone()+year_part+(if suffix_part=="a"{""} else {suffix_part}), selected_entry2);
-                  missing_keys.remove(md_key);
-                }
-            }
-        }
-    }
-
-    // --- 5. Generate Bibliography (Deduplicated and Sorted) ---
-    let style = IndependentStyle::from_xml(csl_style)
-        .map_err(|e| format!("CSL parsing error: {}", e))?;
-    let locale_file = LocaleFile::from_xml(locale)
-        .map_err(|e| format!("Locale parsing error: {}", e))?;
-    let locales = [locale_file.into()];
-
-    let mut bibliography_markdown_lines: Vec<String> = Vec::new();
-    bibliography_markdown_lines.push("### Bibliography".to_string());
-    bibliography_markdown_lines.push("".to_string());
-
-    let mut used_bib_keys: HashSet<String> = HashSet::new();
-    let mut bibliography_items_to_render: Vec<(&Entry,&String)> = Vec::new();
-    
-    // Sort keys to ensure deterministic order
-    let mut sorted_md_keys: Vec<&String> = final_entry_map.keys().collect();
-    sorted_md_keys.sort();
-
-    for md_key in sorted_md_keys {
-        if let Some(entry) = final_entry_map.get(md_key) {
-            if used_bib_keys.insert(entry.key().to_string()) {
-                bibliography_items_to_render.push( (entry,md_key) ) ;
-            }
+    for (md_key, (author_part, year_part, suffix_part)) in unique_citations {
+        if let Some(selected_entry) =
+            resolve_short_key(author_part, year_part, suffix_part, grouped_entries)
+        {
+            final_entry_map.insert(md_key.clone(), selected_entry);
         }
     }
-
-    bibliography_items_to_render.sort_by(|a, b| {
-        let author_a = get_authors_string(a.0);
-        let author_b = get_authors_string(b.0);
-        let year_a = a.0.date().map(|d| d.year.to_string()).unwrap_or_default();
-        let year_b = b.0.date().map(|d| d.year.to_string()).unwrap_or_default();
-
-        author_a
-            .cmp(&author_b)
-            .then_with(|| year_a.cmp(&year_b))
-            .then_with(|| get_entry_title_for_sort(a.0).cmp(&get_entry_title_for_sort(b.0)))
-    });
-
-    for entry in bibliography_items_to_render.iter() {
-        let formatted_entry = format_bib_entry_for_markdown(entry.0, &style, &locales);
-        let line = format!(
-            "#### {}<a href=\"#{}\" id=\"{}\"></a>",
-            formatted_entry,
-            entry.1,
-            entry.1
-        );
-        bibliography_markdown_lines.push(line);
-    }
-
-    let bibliography_content = if bibliography_items_to_render.is_empty() {
-        "### Bibliography".to_string()
-    } else {
-        bibliography_markdown_lines.join("\n")
-    };
-
-    // --- 6. Replace citations in Markdown ---
-    let mut citation_indices: HashMap<String, (usize, String)> = HashMap::new();
-    for (i, entry) in bibliography_items_to_render.iter().enumerate() {
-        citation_indices.insert(entry.0.key().to_string(), (i + 1,entry.1.to_string()));
-    }
-
-    let modified_markdown_content = parse_incomplete_markdown(&citation_regex
-        .replace_all(markdown_input, |caps: &Captures| {
-           //let full_match = caps.get(1).map_or("", |m| m.as_str()).to_string();
-           let author_part = caps.get(2).map_or("", |m| m.as_str()).to_string();
-           let year_part = caps.get(3).map_or("", |m| m.as_str()).to_string();
-           let suffix_part = caps.get(4).map_or("", |m| m.as_str()).to_string();
-           let anchor = author_part.clone()+&year_part+(if suffix_part=="a"{""} else {&suffix_part});
-
-            if let Some(entry) = final_entry_map.get(&anchor) {
-                if let Some((_index,anch)) = citation_indices.get(entry.key()) {
-                    let link = format!("[[{}]]({}#{})", anch, bibliography_link_prefix, anch);
-                    return link;
-                }
-            }
-            ["@", &anchor].join("")
-        })
-        .to_string());
-
-    Ok(ProcessingOutput {
-        modified_markdown: modified_markdown_content,
-        bibliography_markdown: bibliography_content,
-    })
+    final_entry_map
 }
 
-// --- Helper Functions ---
-
 // CORRECTED
 fn get_first_author_last_name(entry: &Entry) -> Option<String> {
     entry
         .authors()
-        .and_then(|authors| authors.get(0))
-        .map(|person| person.name.to_lowercase())
+        .and_then(|authors| authors.first())
+        .map(|person| latex::ascii_fold(&person.name).to_lowercase())
 }
 
+/// The last two digits of the year of an entry or, as Hayagriva YAML often
+/// dates a chapter by its book, of its nearest dated parent.
 fn get_year_yy(entry: &Entry) -> Option<String> {
-    entry.date().and_then(|date| {
+    entry_date(entry).and_then(|date| {
         let year_str = date.year.to_string();
         if year_str.len() >= 2 {
             Some(year_str.chars().skip(year_str.len() - 2).collect())
@@ -911,17 +947,23 @@ fn get_year_yy(entry: &Entry) -> Option<String> {
     })
 }
 
+fn entry_date(entry: &Entry) -> Option<&hayagriva::types::Date> {
+    entry.date().or_else(|| entry.parents().iter().find_map(entry_date))
+}
+
 // CORRECTED
 fn person_to_string(p: &Person) -> String {
     format!(
         "{}{}",
-        &p.name,
+        p.name,
         p.given_name.as_deref().unwrap_or("")
     )
 }
 
+/// Sort key of an entry's authors, ASCII-folded and lowercased like
+/// [`get_first_author_last_name`], so that `Gödel` sorts with `Godel`.
 fn get_authors_string(entry: &Entry) -> String {
-    entry
+    let authors = entry
         .authors()
         .map(|authors| {
             authors
@@ -930,16 +972,168 @@ fn get_authors_string(entry: &Entry) -> String {
                 .collect::<Vec<_>>()
                 .join(", ")
         })
-        .unwrap_or_else(|| "Anonymous".to_string())
+        .unwrap_or_else(|| "Anonymous".to_string());
+    latex::ascii_fold(&authors).to_lowercase()
 }
 
 fn get_entry_title_for_sort(entry: &Entry) -> String {
     entry
         .title()
-        .map(|t| t.to_string().to_lowercase())
+        .map(|t| latex::ascii_fold(&t.to_string()).to_lowercase())
         .unwrap_or_default()
 }
 
+/// Heading used when neither the options, the style nor the locale provide one.
+const DEFAULT_BIBLIOGRAPHY_TITLE: &str = "Bibliography";
+
+// Matches a `<term>` with a body, e.g. `<term name="bibliography">Literaturverzeichnis</term>`.
+// Attributes are captured as a whole since CSL does not fix their order.
+static TERM_PATTERN: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"(?s)<term((?:\s+[\w:-]+\s*=\s*"[^"]*")*)\s*>(.*?)</term>"#).unwrap()
+});
+static TERM_ATTRIBUTE_PATTERN: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"([\w:-]+)\s*=\s*"([^"]*)""#).unwrap());
+static TERM_MULTIPLE_PATTERN: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?s)<multiple>(.*?)</multiple>").unwrap());
+static TERM_SINGLE_PATTERN: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?s)<single>(.*?)</single>").unwrap());
+// `bibliography` is not a CSL 1.0.2 term, so citationberg rejects locales defining it.
+static UNSUPPORTED_TERM_PATTERN: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"(?s)<term\s[^>]*?\bname="bibliography"[^>]*?(?:/>|>.*?</term>)"#).unwrap()
+});
+
+/// Removes terms that citationberg cannot deserialize from a CSL style or locale.
+fn strip_unsupported_terms(xml: &str) -> std::borrow::Cow<'_, str> {
+    UNSUPPORTED_TERM_PATTERN.replace_all(xml, "")
+}
+
+/// Finds the reference list heading among the terms of a CSL style or locale.
+///
+/// `bibliography` wins over `references`, which wins over the plural of `reference`.
+fn find_heading_term(xml: &str) -> Option<String> {
+    let mut found: [Option<String>; 3] = [None, None, None];
+    for cap in TERM_PATTERN.captures_iter(xml) {
+        let attribute = |key: &str| {
+            TERM_ATTRIBUTE_PATTERN
+                .captures_iter(&cap[1])
+                .find(|a| &a[1] == key)
+                .map(|a| a.get(2).map_or("", |m| m.as_str()))
+        };
+        if attribute("form").is_some_and(|form| form != "long") {
+            continue;
+        }
+        let Some(name) = attribute("name") else {
+            continue;
+        };
+        let body = cap.get(2).map_or("", |m| m.as_str());
+        let variant = |pattern: &Regex| {
+            pattern
+                .captures(body)
+                .and_then(|c| c.get(1))
+                .map_or(body, |m| m.as_str())
+        };
+        let (rank, text) = match name {
+            "bibliography" => (0, variant(&TERM_SINGLE_PATTERN)),
+            "references" => (1, variant(&TERM_MULTIPLE_PATTERN)),
+            "reference" => (2, variant(&TERM_MULTIPLE_PATTERN)),
+            _ => continue,
+        };
+        let text = unescape_xml(text.trim());
+        if found[rank].is_none() && !text.is_empty() {
+            found[rank] = Some(capitalize_first(&text));
+        }
+    }
+    found.into_iter().flatten().next()
+}
+
+/// Resolves the bibliography heading: explicit option, then the style's own
+/// locale overrides, then the locale file.
+fn bibliography_title(options: &ProcessingOptions, csl_style: &str, locale: &str) -> Option<String> {
+    options
+        .bibliography_title
+        .clone()
+        .or_else(|| find_heading_term(csl_style))
+        .or_else(|| find_heading_term(locale))
+}
+
+/// The plural of the `reference` term in the first locale for `code`, or for
+/// its base language.
+fn builtin_heading(locales: &[Locale], code: &LocaleCode) -> Option<String> {
+    let exact = locales.iter().find(|l| l.lang.as_ref() == Some(code));
+    let base = locales
+        .iter()
+        .find(|l| l.lang.as_ref().is_some_and(|lang| same_language(lang, code)));
+    let term = exact
+        .or(base)?
+        .term(Term::Other(OtherTerm::Reference), TermForm::Long)?
+        .multiple()?;
+    Some(capitalize_first(term))
+}
+
+fn same_language(a: &LocaleCode, b: &LocaleCode) -> bool {
+    let base = |code: &LocaleCode| code.0.split('-').next().unwrap_or("").to_lowercase();
+    base(a) == base(b)
+}
+
+fn capitalize_first(text: &str) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+fn unescape_xml(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Resolves the parts of a Markdown key to a BibTeX entry, trying the exact
+/// author name first and then the closest name with the same year.
+fn resolve_short_key<'a>(
+    author_part: &str,
+    year_part: &str,
+    suffix_part: &str,
+    grouped_entries: &HashMap<EntryGroupKey, Vec<&'a Entry>>,
+) -> Option<&'a Entry> {
+    let md_author_lc = author_part.to_lowercase();
+    let index = suffix_to_index(suffix_part);
+
+    // --- 4a. Try Exact Match ---
+    let lookup_key = (md_author_lc.clone(), year_part.to_string());
+    if let Some(selected_entry) = grouped_entries.get(&lookup_key).and_then(|group| group.get(index)) {
+        return Some(selected_entry);
+    }
+
+    // --- 4b. Try Fuzzy Match if Exact Failed ---
+    const FUZZY_MATCH_THRESHOLD: usize = 2; // Stricter threshold
+    let mut best_fuzzy_match: Option<(usize, &Vec<&'a Entry>)> = None;
+
+    for ((last_name_lc, year_yy), group) in grouped_entries {
+        if year_yy == year_part {
+            let distance = levenshtein(&md_author_lc, last_name_lc);
+            if distance <= FUZZY_MATCH_THRESHOLD
+                && best_fuzzy_match.is_none_or(|(best_distance, _)| distance < best_distance)
+            {
+                best_fuzzy_match = Some((distance, group));
+            }
+        }
+    }
+
+    best_fuzzy_match.and_then(|(_dist, group)| group.get(index).copied())
+}
+
+/// Normalised Markdown key of a citation match, dropping the implicit `a` suffix.
+fn citation_key(caps: &Captures) -> String {
+    let author_part = caps.get(2).map_or("", |m| m.as_str());
+    let year_part = caps.get(3).map_or("", |m| m.as_str());
+    let suffix_part = caps.get(4).map_or("", |m| m.as_str());
+    author_part.to_string() + year_part + if suffix_part == "a" { "" } else { suffix_part }
+}
+
 fn suffix_to_index(suffix: &str) -> usize {
     if suffix.is_empty() {
         0
@@ -948,24 +1142,75 @@ fn suffix_to_index(suffix: &str) -> usize {
     }
 }
 
-fn format_bib_entry_for_markdown(
-    entry: &Entry,
+/// Renders one note citation per group of entries, in document order, with a
+/// single driver so that repeated citations get the style's short form or "Ibid.".
+fn format_notes_for_markdown(
+    citations: &[Vec<&Entry>],
+    first_note: usize,
+    style: &IndependentStyle,
+    locale: Option<LocaleCode>,
+    locales: &[Locale],
+) -> Vec<String> {
+    let mut driver = BibliographyDriver::new();
+    for (i, entries) in citations.iter().enumerate() {
+        driver.citation(CitationRequest::new(
+            entries.iter().map(|&entry| CitationItem::with_entry(entry)).collect(),
+            style,
+            locale.clone(),
+            locales,
+            Some(first_note + i),
+        ));
+    }
+
+    let request = BibliographyRequest { style, locale, locale_files: locales };
+    driver
+        .finish(request)
+        .citations
+        .into_iter()
+        .map(|citation| citation.citation.to_string())
+        .collect()
+}
+
+/// Renders entries with a single driver, so that numbering and disambiguation
+/// are shared by one bibliography, and returns them in input order.
+fn format_bib_entries_for_markdown(
+    entries: &[&Entry],
     style: &IndependentStyle,
+    locale: Option<LocaleCode>,
     locales: &[Locale],
-) -> String {
+) -> Vec<String> {
     let mut driver = BibliographyDriver::new();
-    driver.citation(CitationRequest::from_items(
-        vec![CitationItem::with_entry(entry)],
-        style,
-        locales,
-    ));
+    for &entry in entries {
+        driver.citation(CitationRequest::new(
+            vec![CitationItem::with_entry(entry)],
+            style,
+            locale.clone(),
+            locales,
+            None,
+        ));
+    }
 
-    let request = BibliographyRequest { style, locale: None, locale_files: locales };
+    let request = BibliographyRequest { style, locale, locale_files: locales };
     let result = driver.finish(request);
 
-    result
+    let rendered: HashMap<String, String> = result
         .bibliography
-        .and_then(|bib| bib.items.into_iter().next())
-        .map(|item| item.content.to_string())
-        .unwrap_or_default()
-}
\ No newline at end of file
+        .map(|bib| {
+            bib.items
+                .into_iter()
+                .map(|item| {
+                    let content = match item.first_field {
+                        Some(first_field) => format!("{} {}", first_field, item.content),
+                        None => item.content.to_string(),
+                    };
+                    (item.key, content)
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    entries
+        .iter()
+        .map(|entry| rendered.get(entry.key()).cloned().unwrap_or_default())
+        .collect()
+}
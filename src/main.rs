@@ -1,64 +1,391 @@
 // cargo build --target=wasm32-wasip1
 // wasmer run markdown_bib_processor.wasm --mapdir /:. -- --markdown md.md     --bibtex bib.bib     --csl chicago.csl     --locale locales-en-US.xml
 
-use clap::Parser;
+use clap::{Args as ClapArgs, Parser, Subcommand};
 use std::fs;
-use std::path::PathBuf;
+use std::io::Read;
+use std::path::{Path, PathBuf};
 
 // Import the function from the library crate
-use markdown_bib_processor::process_markdown_and_bibtex;
+use markdown_bib_processor::{
+    extract_cited_bibliography, process_markdown_with_bibliographies, repair_markdown_with_edits,
+    BibliographyFormat, BibliographySource, DuplicatePrecedence, FrontMatter, ProcessingOptions,
+    RepairOptions, RepairRules,
+};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
-struct Args {
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    args: Args,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Write a bibliography file with only the entries the Markdown file cites
+    Extract(ExtractArgs),
+    /// Complete the unclosed Markdown read from standard input, such as partial LLM output, and write it to standard output
+    Repair(RepairArgs),
+}
+
+#[derive(ClapArgs, Debug)]
+struct RepairArgs {
+    /// URL given to links cut off before their closing bracket or parenthesis
+    #[arg(long)]
+    incomplete_link_url: Option<String>,
+
+    /// Complete partial images like links, showing their alt text, instead of holding them back
+    #[arg(long)]
+    keep_image_alt: bool,
+
+    /// Repairs to make: all, none, or a list of emphasis, code, math, links, tables and citations
+    #[arg(long, default_value = "all")]
+    rules: RepairRules,
+
+    /// Only repair the block the input ends with, leaving alone what opened before the last blank line
+    #[arg(long)]
+    trailing_block_only: bool,
+
+    /// List the edits made (closers added, partial text held back) on standard error
+    #[arg(long)]
+    edits: bool,
+}
+
+#[derive(ClapArgs, Debug)]
+struct ExtractArgs {
     /// Path to the input Markdown file
     #[arg(long)]
     markdown: PathBuf,
 
-    /// Path to the input BibTeX file
+    /// Path to the master bibliography file, a directory or a glob pattern (repeatable; overrides the front matter's `bibliography`)
+    #[arg(long)]
+    bibtex: Vec<PathBuf>,
+
+    /// Which file wins when several define the same key: first, last or error
+    #[arg(long, default_value = "first")]
+    duplicate_precedence: DuplicatePrecedence,
+
+    /// Format of the extracted file: bibtex, csl-json, csl-yaml, ris or yaml
+    #[arg(long, default_value = "bibtex", value_parser = export_format)]
+    format: BibliographyFormat,
+
+    /// Where to write the extracted file (defaults to standard output)
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(ClapArgs, Debug)]
+struct Args {
+    /// Path to the input Markdown file
+    #[arg(long, required = true)]
+    markdown: Option<PathBuf>,
+
+    /// Path to an input bibliography file (BibTeX, CSL-JSON, Hayagriva YAML, RIS or EndNote XML), a directory or a glob pattern (repeatable; overrides the front matter's `bibliography`)
+    #[arg(long)]
+    bibtex: Vec<PathBuf>,
+
+    /// Which file wins when several define the same key: first, last or error
+    #[arg(long, default_value = "first")]
+    duplicate_precedence: DuplicatePrecedence,
+
+    /// Path to the CSL style file (e.g., chicago-author-date.csl); overrides the front matter's `csl`
+    #[arg(long)]
+    csl: Option<PathBuf>,
+
+    /// Path to the CSL locale file (e.g., en-US.xml); without it, hayagriva's built-in locales are used
+    #[arg(long)]
+    locale: Option<PathBuf>,
+
+    /// Locale code such as de-DE, instead of the style's default locale; overrides the front matter's `lang`
+    #[arg(long)]
+    lang: Option<String>,
+
+    /// Whether citations link to the bibliography (true or false); overrides the front matter's `link-citations`
+    #[arg(long)]
+    link_citations: Option<bool>,
+
+    /// Leave the YAML front matter out of the output
+    #[arg(long)]
+    strip_front_matter: bool,
+
+    /// Do not read settings from the YAML front matter
+    #[arg(long)]
+    ignore_front_matter: bool,
+
+    /// Heading of the bibliography section (defaults to the front matter's `reference-section-title`, then the locale's term)
+    #[arg(long)]
+    bibliography_title: Option<String>,
+
+    /// Start a new reference section, with its own bibliography, at each heading of this level or above
+    #[arg(long)]
+    bibliography_scope_level: Option<usize>,
+
+    /// List an entry without citing it: a BibTeX or Markdown key, `*` for all entries, or a filter such as `keyword=primary` (repeatable; overrides the front matter's `nocite`)
     #[arg(long)]
-    bibtex: PathBuf,
+    nocite: Vec<String>,
 
-    /// Path to the CSL style file (e.g., chicago-author-date.csl)
+    /// Link each bibliography entry back to the places it is cited
     #[arg(long)]
-    csl: PathBuf,
+    backlinks: bool,
 
-    /// Path to the CSL locale file (e.g., en-US.xml)
+    /// Export the entries of the bibliography as csl-json, csl-yaml, bibtex, ris or yaml, printed instead of the Markdown unless --export-output is given
+    #[arg(long, value_parser = export_format)]
+    export_format: Option<BibliographyFormat>,
+
+    /// Where to write the export
+    #[arg(long, requires = "export_format")]
+    export_output: Option<PathBuf>,
+
+    /// Repairs to make to Markdown cut off mid-stream: all, none, or a list of emphasis, code, math, links, tables and citations
+    #[arg(long, default_value = "all")]
+    repair: RepairRules,
+
+    /// Only repair the block the Markdown ends with, leaving alone what opened before the last blank line
     #[arg(long)]
-    locale: PathBuf,
+    repair_trailing_block_only: bool,
+}
+
+/// Parses the formats a bibliography can be written in: all the readable
+/// ones but EndNote XML.
+fn export_format(s: &str) -> Result<BibliographyFormat, String> {
+    match s.parse()? {
+        BibliographyFormat::EndNoteXml => Err(
+            "EndNote XML cannot be written; use csl-json, csl-yaml, bibtex, ris or yaml".to_string(),
+        ),
+        format => Ok(format),
+    }
 }
 
 fn main() -> std::io::Result<()> {
-    let args = Args::parse();
+    let cli = Cli::parse();
+    match cli.command {
+        Some(Command::Extract(args)) => extract(args),
+        Some(Command::Repair(args)) => repair(args),
+        None => process(cli.args),
+    }
+}
+
+fn extract(args: ExtractArgs) -> std::io::Result<()> {
+    let markdown_input = fs::read_to_string(&args.markdown)?;
+    // Only the front matter's `bibliography` is used, when none is given.
+    let front_matter = if args.bibtex.is_empty() {
+        read_front_matter(&markdown_input, &args.markdown)?
+    } else {
+        FrontMatter::default()
+    };
+    let bibliographies = read_bibliographies(&args.bibtex, &front_matter, &args.markdown)?;
+
+    let extracted = extract_cited_bibliography(
+        &markdown_input,
+        &bibliographies,
+        args.format,
+        args.duplicate_precedence,
+    )
+    .map_err(|e| std::io::Error::other(format!("Error extracting entries: {}", e)))?;
+    match args.output {
+        Some(path) => fs::write(path, extracted),
+        None => {
+            print!("{}", extracted);
+            Ok(())
+        }
+    }
+}
+
+fn repair(args: RepairArgs) -> std::io::Result<()> {
+    let mut input = String::new();
+    std::io::stdin().read_to_string(&mut input)?;
+    let mut options = RepairOptions::default();
+    if let Some(url) = args.incomplete_link_url {
+        options.incomplete_link_url = url;
+    }
+    options.keep_image_alt = args.keep_image_alt;
+    options.rules = args.rules;
+    options.trailing_block_only = args.trailing_block_only;
+
+    // The closers belong before the final newline of the input, not after it.
+    let (text, newline) = match input.strip_suffix('\n') {
+        Some(text) => (text, "\n"),
+        None => (input.as_str(), ""),
+    };
+    let (repaired, edits) = repair_markdown_with_edits(text, &options);
+    print!("{}{}", repaired, newline);
+    if args.edits {
+        for edit in edits {
+            if edit.removed.is_empty() {
+                eprintln!("{}: {} {:?}", edit.position, edit.kind, edit.inserted);
+            } else {
+                eprintln!("{}: {} {:?}", edit.position, edit.kind, edit.removed);
+            }
+        }
+    }
+    Ok(())
+}
 
+fn process(args: Args) -> std::io::Result<()> {
     // Read the content from the files specified in the command-line arguments
-    let markdown_input = fs::read_to_string(args.markdown)?;
-    let bibtex_input = fs::read_to_string(args.bibtex)?;
-    let csl_input = fs::read_to_string(args.csl)?;
-    let locale_input = fs::read_to_string(args.locale)?;
+    // or, failing that, in the front matter, relative to the Markdown file.
+    let markdown_path = args.markdown.unwrap_or_default();
+    let markdown_input = fs::read_to_string(&markdown_path)?;
+    let front_matter = if args.ignore_front_matter {
+        FrontMatter::default()
+    } else {
+        read_front_matter(&markdown_input, &markdown_path)?
+    };
+    let bibliographies = read_bibliographies(&args.bibtex, &front_matter, &markdown_path)?;
+    let csl_path = match args.csl.or_else(|| front_matter.csl_path(&markdown_path)) {
+        Some(path) => path,
+        None => {
+            return Err(std::io::Error::other(
+                "no CSL style: pass --csl or set `csl` in the front matter",
+            ))
+        }
+    };
+    let csl_input = fs::read_to_string(csl_path)?;
+    let locale_input = match args.locale {
+        Some(path) => fs::read_to_string(path)?,
+        None => String::new(),
+    };
+
+    // The library fills in the settings left unset from the front matter.
+    let options = ProcessingOptions {
+        bibliography_title: args.bibliography_title,
+        bibliography_scope_level: args.bibliography_scope_level,
+        nocite: args.nocite,
+        backlinks: args.backlinks,
+        bibliography_format: None,
+        duplicate_precedence: args.duplicate_precedence,
+        lang: args.lang,
+        link_citations: args.link_citations,
+        read_front_matter: !args.ignore_front_matter,
+        strip_front_matter: args.strip_front_matter,
+        export_format: args.export_format,
+        repair: RepairOptions {
+            rules: args.repair,
+            trailing_block_only: args.repair_trailing_block_only,
+            ..RepairOptions::default()
+        },
+    };
 
     // Call the library function to process the inputs
-    match process_markdown_and_bibtex(
+    match process_markdown_with_bibliographies(
         &markdown_input,
-        &bibtex_input,
+        &bibliographies,
         "", // Using an empty string for the link prefix
         &csl_input,
         &locale_input,
+        &options,
     ) {
         Ok(output) => {
+            for conflict in &output.merge_conflicts {
+                eprintln!("Warning: {}", conflict);
+            }
+            for field in &output.unmapped_fields {
+                eprintln!(
+                    "Warning: {}: could not translate `{}` ({})",
+                    field.key, field.field, field.value
+                );
+            }
+            if let Some(exported) = &output.exported_bibliography {
+                match &args.export_output {
+                    Some(path) => fs::write(path, exported)?,
+                    None => {
+                        print!("{}", exported);
+                        return Ok(());
+                    }
+                }
+            }
             // Combine the processed markdown and the bibliography and print to console
-            let final_document = format!(
-                "{}\n\n{}",
-                output.modified_markdown, output.bibliography_markdown
-            );
-            println!("{}", final_document);
+            if output.bibliography_markdown.is_empty() {
+                println!("{}", output.modified_markdown);
+            } else {
+                let final_document = format!(
+                    "{}\n\n{}",
+                    output.modified_markdown, output.bibliography_markdown
+                );
+                println!("{}", final_document);
+            }
         }
         Err(e) => {
             eprintln!("Error processing files: {}", e);
             // Return an I/O error to terminate the process
-            return Err(std::io::Error::new(std::io::ErrorKind::Other, e));
+            return Err(std::io::Error::other(e));
         }
     }
 
     Ok(())
 }
+
+/// Parses the front matter, reporting an error against the Markdown file.
+fn read_front_matter(markdown_input: &str, markdown_path: &Path) -> std::io::Result<FrontMatter> {
+    FrontMatter::parse(markdown_input).map_err(|e| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{}: {}", markdown_path.display(), e),
+        )
+    })
+}
+
+/// Reads the bibliographies given on the command line or, failing that, in
+/// the front matter, relative to the Markdown file.
+fn read_bibliographies(
+    paths: &[PathBuf],
+    front_matter: &FrontMatter,
+    markdown_path: &Path,
+) -> std::io::Result<Vec<BibliographySource>> {
+    let paths: Vec<PathBuf> = if paths.is_empty() {
+        front_matter.bibliography_paths(markdown_path)
+    } else {
+        paths.to_vec()
+    };
+
+    let mut bibliographies = Vec::new();
+    for path in bibliography_paths(&paths)? {
+        bibliographies.push(BibliographySource {
+            name: path.display().to_string(),
+            content: fs::read_to_string(&path)?,
+            format: BibliographyFormat::from_path(&path),
+        });
+    }
+    Ok(bibliographies)
+}
+
+/// Expands directories to the bibliography files they contain and glob
+/// patterns to the files they match, keeping the order given.
+fn bibliography_paths(paths: &[PathBuf]) -> std::io::Result<Vec<PathBuf>> {
+    let mut expanded = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let mut files: Vec<PathBuf> = fs::read_dir(path)?
+                .filter_map(|entry| Some(entry.ok()?.path()))
+                .filter(|file| file.is_file() && BibliographyFormat::from_path(file).is_some())
+                .collect();
+            files.sort();
+            expanded.extend(files);
+        } else if !path.exists() && is_glob(path) {
+            let pattern = path.to_string_lossy();
+            let matches = glob::glob(&pattern)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+            let files: Vec<PathBuf> =
+                matches.filter_map(Result::ok).filter(|file| file.is_file()).collect();
+            // A pattern matching nothing is as likely a typo as a missing file.
+            if files.is_empty() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("{}: no bibliography file matches", pattern),
+                ));
+            }
+            expanded.extend(files);
+        } else {
+            expanded.push(path.clone());
+        }
+    }
+    Ok(expanded)
+}
+
+fn is_glob(path: &Path) -> bool {
+    path.to_string_lossy().contains(['*', '?', '['])
+}
@@ -0,0 +1,310 @@
+//! Completes Markdown cut off mid-stream, such as a chunk of LLM output, so
+//! that it renders without dangling markers: `**bold` becomes `**bold**`,
+//! `[text` a link, and so on. Complete documents are left as they are.
+
+mod scanner;
+
+use scanner::Scanner;
+use std::fmt;
+use std::str::FromStr;
+
+/// Options for [`repair_markdown`].
+#[derive(Debug, Clone)]
+pub struct RepairOptions {
+    /// URL given to a link cut off before its closing `)` (in place of
+    /// any partial URL) or `]`, so that renderers can show it as pending.
+    pub incomplete_link_url: String,
+    /// Completes a partial image like a link, with the placeholder URL, so
+    /// that its alt text is shown; by default the image is held back, with
+    /// anything after it.
+    pub keep_image_alt: bool,
+    /// The repairs to make; all of them by default.
+    pub rules: RepairRules,
+    /// Leaves alone what opened before the last blank line, such as a code
+    /// block or `$$` left open on purpose, and only repairs the block the
+    /// text ends with.
+    pub trailing_block_only: bool,
+    /// Also holds back a citation key being written outside brackets
+    /// (`see @Coh`), as in a document whose citations are replaced; by
+    /// default only `[@Coh` is, since a bare `@name` may be a handle.
+    pub bare_citation_keys: bool,
+    /// Takes the text to end mid-token, as a stream does: a final `$x` is
+    /// closed as math, a table row gets its missing cells or delimiter row,
+    /// and bare citation keys are held back. Without it those are taken to
+    /// be finished text.
+    pub partial_tokens: bool,
+}
+
+impl Default for RepairOptions {
+    fn default() -> Self {
+        Self {
+            incomplete_link_url: "streamdown:incomplete-link".to_string(),
+            keep_image_alt: false,
+            rules: RepairRules::ALL,
+            trailing_block_only: false,
+            bare_citation_keys: false,
+            partial_tokens: true,
+        }
+    }
+}
+
+/// Which kinds of construct [`repair_markdown`] completes. What a disabled
+/// rule would have closed is still recognised, so that the markers inside a
+/// code span stay code even when code spans are not closed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RepairRules {
+    /// `*`, `_`, `**`, `__` and `~~`.
+    pub emphasis: bool,
+    /// Code spans and fenced code blocks.
+    pub code: bool,
+    /// `$`, `$$`, `\(` and `\[` math.
+    pub math: bool,
+    /// Links, reference links, autolinks and images.
+    pub links: bool,
+    /// Table rows and delimiter rows.
+    pub tables: bool,
+    /// Partial citation keys and bracketed citations.
+    pub citations: bool,
+}
+
+impl RepairRules {
+    pub const ALL: Self = Self {
+        emphasis: true,
+        code: true,
+        math: true,
+        links: true,
+        tables: true,
+        citations: true,
+    };
+
+    /// No repair at all: the text is returned as it is.
+    pub const NONE: Self = Self {
+        emphasis: false,
+        code: false,
+        math: false,
+        links: false,
+        tables: false,
+        citations: false,
+    };
+}
+
+impl Default for RepairRules {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+impl FromStr for RepairRules {
+    type Err = String;
+
+    /// `all`, `none`, or a comma-separated list of rules, such as
+    /// `emphasis,code`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "all" => return Ok(Self::ALL),
+            "none" | "" => return Ok(Self::NONE),
+            _ => {}
+        }
+        let mut rules = Self::NONE;
+        for rule in s.split(',') {
+            let enabled = match rule.trim().to_lowercase().as_str() {
+                "emphasis" => &mut rules.emphasis,
+                "code" => &mut rules.code,
+                "math" => &mut rules.math,
+                "links" => &mut rules.links,
+                "tables" => &mut rules.tables,
+                "citations" => &mut rules.citations,
+                _ => {
+                    return Err(format!(
+                        "unknown repair rule `{}`, expected all, none or a list of emphasis, code, math, links, tables and citations",
+                        rule.trim()
+                    ))
+                }
+            };
+            *enabled = true;
+        }
+        Ok(rules)
+    }
+}
+
+/// Repairs Markdown as it streams in, chunk by chunk. Unlike
+/// [`repair_markdown`], which scans the whole text each time, it keeps the state of
+/// the open constructs (emphasis, code spans and fences, display math,
+/// links, table rows) between chunks, so each character is scanned about
+/// once; showing the repaired text only looks back over the last word and
+/// any link text still open.
+///
+/// ```
+/// use markdown_bib_processor::{RepairOptions, StreamingRepair};
+///
+/// let mut repair = StreamingRepair::new(RepairOptions::default());
+/// assert_eq!(repair.push("Some **bold").to_string(), "Some **bold**");
+/// assert_eq!(repair.push(" and *italic").to_string(), "Some **bold and *italic***");
+/// assert_eq!(repair.push("* text**.").to_string(), "Some **bold and *italic* text**.");
+/// ```
+#[derive(Debug, Clone)]
+pub struct StreamingRepair {
+    options: RepairOptions,
+    text: String,
+    scanner: Scanner,
+}
+
+/// The text received so far, repaired: a prefix of it (a partial image is
+/// held back) followed by the closers of whatever is still open, which go
+/// before the prefix's trailing whitespace (`*a\n` becomes `*a*\n`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepairedView<'a> {
+    pub text: &'a str,
+    pub closers: String,
+    pub trailing_whitespace: &'a str,
+    /// What was held back and what each closer is for.
+    pub edits: Vec<RepairEdit>,
+}
+
+/// A change the repair made to the text, so that editors can highlight the
+/// synthetic closers and drop them once the real text arrives.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepairEdit {
+    pub kind: RepairEditKind,
+    /// Byte offset in the input: where the inserted text goes, after that of
+    /// earlier edits at the same offset, or where the removed text starts.
+    pub position: usize,
+    /// Text added, such as a closing `**`; empty for a removal.
+    pub inserted: String,
+    /// Text of the input held back, such as a partial image; empty for an
+    /// insertion.
+    pub removed: String,
+}
+
+impl RepairEdit {
+    /// An insertion, positioned once the text it follows is known.
+    fn inserted(kind: RepairEditKind, text: String) -> Self {
+        Self { kind, position: 0, inserted: text, removed: String::new() }
+    }
+}
+
+/// What a [`RepairEdit`] does; displayed as, e.g., "closed bold".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RepairEditKind {
+    ClosedItalic,
+    ClosedBold,
+    ClosedStrikethrough,
+    /// A code span.
+    ClosedCode,
+    /// A fenced code block.
+    ClosedCodeBlock,
+    /// `$`, `\(` math.
+    ClosedMath,
+    /// `$$`, `\[` math.
+    ClosedDisplayMath,
+    /// A link or image, given the placeholder URL.
+    CompletedLink,
+    ClosedAutolink,
+    ClosedCitation,
+    /// Empty cells up to the column count.
+    CompletedTableRow,
+    AddedDelimiterRow,
+    RemovedPartialImage,
+    /// A URL replaced by the placeholder.
+    RemovedPartialUrl,
+    /// A reference label replaced by the placeholder URL.
+    RemovedPartialReference,
+    RemovedPartialCitation,
+    RemovedPartialTableRow,
+    RemovedPartialDelimiterRow,
+}
+
+impl fmt::Display for RepairEditKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::ClosedItalic => "closed italic",
+            Self::ClosedBold => "closed bold",
+            Self::ClosedStrikethrough => "closed strikethrough",
+            Self::ClosedCode => "closed code span",
+            Self::ClosedCodeBlock => "closed code block",
+            Self::ClosedMath => "closed math",
+            Self::ClosedDisplayMath => "closed display math",
+            Self::CompletedLink => "completed link",
+            Self::ClosedAutolink => "closed autolink",
+            Self::ClosedCitation => "closed citation",
+            Self::CompletedTableRow => "completed table row",
+            Self::AddedDelimiterRow => "added delimiter row",
+            Self::RemovedPartialImage => "removed partial image",
+            Self::RemovedPartialUrl => "removed partial URL",
+            Self::RemovedPartialReference => "removed partial reference",
+            Self::RemovedPartialCitation => "removed partial citation",
+            Self::RemovedPartialTableRow => "removed partial table row",
+            Self::RemovedPartialDelimiterRow => "removed partial delimiter row",
+        })
+    }
+}
+
+impl fmt::Display for RepairedView<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}{}", self.text, self.closers, self.trailing_whitespace)
+    }
+}
+
+impl StreamingRepair {
+    pub fn new(options: RepairOptions) -> Self {
+        let scanner = Scanner::new(&options);
+        Self { options, text: String::new(), scanner }
+    }
+
+    /// Appends a chunk and returns the repaired text.
+    pub fn push(&mut self, chunk: &str) -> RepairedView<'_> {
+        self.text.push_str(chunk);
+        self.scanner.scan(&self.text, false);
+        self.view()
+    }
+
+    /// The repaired text, without appending anything.
+    pub fn view(&self) -> RepairedView<'_> {
+        // Whatever was held back for lack of lookahead is scanned as the end
+        // of the text, on a copy of the state.
+        let mut scanner = self.scanner.clone();
+        scanner.scan(&self.text, true);
+        repaired_view(&self.text, &scanner, &self.options)
+    }
+
+    /// The text received so far, as is.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+/// Parses markdown text and completes incomplete tokens to prevent partial
+/// rendering, with the default [`RepairOptions`].
+pub fn parse_incomplete_markdown(text: &str) -> String {
+    repair_markdown(text, &RepairOptions::default())
+}
+
+/// Completes the incomplete tokens at the end of a (possibly partial)
+/// Markdown text. Needs no bibliography: this is the repair step
+/// [`crate::process_markdown_and_bibtex`] runs before replacing citations.
+///
+/// Everything still open at the end is closed, innermost first, so that the
+/// result nests properly: `~~strike **bold` becomes `~~strike **bold**~~`.
+pub fn repair_markdown(text: &str, options: &RepairOptions) -> String {
+    repair_markdown_with_edits(text, options).0
+}
+
+/// [`repair_markdown`], also returning the edits it made, in order.
+pub fn repair_markdown_with_edits(text: &str, options: &RepairOptions) -> (String, Vec<RepairEdit>) {
+    let mut scanner = Scanner::new(options);
+    scanner.scan(text, true);
+    let view = repaired_view(text, &scanner, options);
+    (view.to_string(), view.edits)
+}
+
+/// The view of `text` once `scanner` has scanned all of it.
+fn repaired_view<'a>(text: &'a str, scanner: &Scanner, options: &RepairOptions) -> RepairedView<'a> {
+    let (visible, mut edits) = scanner.closers(text, options);
+    let closers: String = edits.iter().map(|edit| edit.inserted.as_str()).collect();
+    let text = &text[..visible];
+    let body = if closers.is_empty() { text } else { text.trim_end() };
+    for edit in edits.iter_mut().filter(|edit| !edit.inserted.is_empty()) {
+        edit.position = body.len();
+    }
+    RepairedView { text: body, closers, trailing_whitespace: &text[body.len()..], edits }
+}
@@ -0,0 +1,1474 @@
+//! The tokenizer behind [`super::repair_markdown`] and
+//! [`super::StreamingRepair`]: a single pass that tracks the inline
+//! constructs, code fence and math block open at the end of the text seen so
+//! far, innermost last.
+//!
+//! Scanning is resumable. A construct whose meaning depends on text that has
+//! not arrived yet, such as a `*` run at the very end (`**` or `*`?), is left
+//! unscanned until more text comes, so each character is scanned about once.
+
+use super::{RepairEdit, RepairEditKind, RepairOptions};
+
+/// An inline construct waiting for its closer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Marker {
+    /// `*` or `_`.
+    Emphasis(char),
+    /// `**` or `__`.
+    Strong(char),
+    /// `~~`.
+    Strikethrough,
+    /// A code span opened by this many backticks.
+    Code(usize),
+    /// `[` or `![`, before the `]`.
+    LinkText { image: bool },
+    /// `](`, before the `)`, with the depth of parentheses inside the URL.
+    LinkUrl { depth: usize, image: bool },
+    /// `][`, before the `]` of a reference label.
+    LinkRef { image: bool },
+    /// `<` and a URI scheme, before the `>`.
+    Autolink,
+    /// `$`, with the start of the line it is on.
+    InlineMath { line_start: usize },
+    /// `\(`, before the `\)`.
+    LatexMath,
+}
+
+impl Marker {
+    fn delimiter(self) -> Option<(char, usize)> {
+        match self {
+            Marker::Emphasis(c) => Some((c, 1)),
+            Marker::Strong(c) => Some((c, 2)),
+            Marker::Strikethrough => Some(('~', 2)),
+            _ => None,
+        }
+    }
+
+    fn is_link(self) -> bool {
+        matches!(
+            self,
+            Marker::LinkText { .. }
+                | Marker::LinkUrl { .. }
+                | Marker::LinkRef { .. }
+                | Marker::Autolink
+        )
+    }
+
+    fn is_image(self) -> bool {
+        matches!(
+            self,
+            Marker::LinkText { image: true }
+                | Marker::LinkUrl { image: true, .. }
+                | Marker::LinkRef { image: true }
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct Open {
+    pub marker: Marker,
+    /// Byte offset of the opener.
+    pub start: usize,
+    /// Byte offset just after the opener.
+    pub content_start: usize,
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct Fence {
+    /// Byte offset of the line of the opening fence.
+    pub start: usize,
+    pub marker: char,
+    pub len: usize,
+    /// Spaces before the opening fence, repeated before the closing one.
+    pub indent: usize,
+    /// Whether the line being scanned was found not to close the block
+    /// before it ended, so that the rest of it is skipped.
+    pub plain_line: bool,
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct DisplayMath {
+    /// Byte offset of the opening `$$` or `\[`.
+    pub start: usize,
+    /// Whether a line break follows the opening `$$`.
+    pub multiline: bool,
+    /// Opened by `\[` rather than `$$`, and closed by `\]`.
+    pub bracket: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(super) struct Scanner {
+    /// Byte offset of the first character not scanned yet.
+    pos: usize,
+    /// Byte offset of the start of the line `pos` is on.
+    line_start: usize,
+    /// Byte offset just after the last character that is not whitespace or
+    /// a marker; openers before it have content.
+    last_content: usize,
+    pub stack: Vec<Open>,
+    pub fence: Option<Fence>,
+    pub math: Option<DisplayMath>,
+    /// At the end of the text, the rest of a closer that was cut off, such as
+    /// the second `*` of `**bold*`.
+    partial_closer: String,
+    /// A `$` or `<` found not to open math or an autolink, because the
+    /// paragraph or the URL ended first.
+    plain_opener: Option<usize>,
+    /// The number of columns of the table the current line belongs to.
+    table: Option<usize>,
+    /// The number of cells of the previous line, if it could be the header
+    /// row of a table.
+    header: Option<usize>,
+    /// Byte offset just after the last blank line.
+    block_start: usize,
+    /// Byte offset up to which `row` and `word_start` have been counted.
+    /// Scanning can step back, to scan an unopened `$` or `<` again, but
+    /// counts each character once.
+    counted: usize,
+    /// The current line, as a table row.
+    row: RowCount,
+    /// Byte offset of the run of ASCII letters and digits the text scanned
+    /// so far ends with.
+    word_start: usize,
+    /// Whether what opened before the last blank line is left alone, which
+    /// needs `trailing` to be kept.
+    trailing_block_only: bool,
+    /// Inside a code block or display math, the text after the last blank
+    /// line scanned on its own, as if the block or math were plain text.
+    trailing: Option<Box<Scanner>>,
+}
+
+/// What a table needs to know of the current line, counted as it is
+/// scanned rather than from the whole line each time it is shown.
+#[derive(Debug, Clone, Copy, Default)]
+struct RowCount {
+    /// Unescaped pipes.
+    pipes: usize,
+    /// Whether the last character is a backslash escaping the next one.
+    escaped: bool,
+    /// Byte offset of the first character that cannot be part of a
+    /// delimiter row.
+    not_delimiter: Option<usize>,
+}
+
+impl Scanner {
+    pub fn new(options: &RepairOptions) -> Self {
+        Self { trailing_block_only: options.trailing_block_only, ..Self::default() }
+    }
+
+    /// Scans `text` from where the last call stopped. Unless `at_end`, a
+    /// construct that the next characters could change is left for the next
+    /// call; with `at_end`, the text is taken to end there.
+    pub fn scan(&mut self, text: &str, at_end: bool) {
+        self.scan_blocks(text, at_end);
+        if let Some(trailing) = self.trailing.as_mut() {
+            trailing.scan(text, at_end);
+        }
+    }
+
+    fn scan_blocks(&mut self, text: &str, at_end: bool) {
+        while self.pos < text.len() {
+            let scanned = if self.fence.is_some() {
+                self.scan_fenced_line(text, at_end)
+            } else if self.pos == self.line_start && self.math.is_none() {
+                match self.open_fence(text, at_end) {
+                    Some(true) => Some(()),
+                    Some(false) => self.scan_inline(text, at_end),
+                    None => None,
+                }
+            } else if self.math.is_some() {
+                self.scan_math(text, at_end)
+            } else {
+                self.scan_inline(text, at_end)
+            };
+            if scanned.is_none() {
+                return;
+            }
+            self.count(text);
+        }
+    }
+
+    /// Counts the characters scanned since the last call into `row` and
+    /// `word_start`.
+    fn count(&mut self, text: &str) {
+        if self.pos <= self.counted {
+            return;
+        }
+        for (offset, c) in text[self.counted..self.pos].char_indices() {
+            if !c.is_ascii_alphanumeric() {
+                self.word_start = self.counted + offset + c.len_utf8();
+            }
+            let row = &mut self.row;
+            match c {
+                '\n' => *row = RowCount::default(),
+                '|' if !row.escaped => row.pipes += 1,
+                '|' | ':' | '-' | ' ' | '\t' | '\r' => {}
+                _ => {
+                    row.not_delimiter.get_or_insert(self.counted + offset);
+                }
+            }
+            row.escaped = c == '\\' && !row.escaped;
+        }
+        self.counted = self.pos;
+    }
+
+    /// The length of `text` to show and the edits that repair it: what is
+    /// cut from the end, and the closers to append, innermost first, before
+    /// any trailing whitespace. The closers' positions are left to the caller.
+    pub fn closers(&self, text: &str, options: &RepairOptions) -> (usize, Vec<RepairEdit>) {
+        if let Some(trailing) = self.trailing.as_ref().filter(|_| options.trailing_block_only) {
+            return trailing.closers(text, options);
+        }
+        let rules = &options.rules;
+        let mut edits = Vec::new();
+        // With `trailing_block_only`, what opened before the last blank line
+        // is taken to be meant as written.
+        let in_scope = |start: usize| !options.trailing_block_only || start >= self.block_start;
+        let stack = &self.stack[self.stack.partition_point(|open| !in_scope(open.start))..];
+
+        // A partial image cannot be shown, nor anything after it, unless its
+        // alt text is kept.
+        let mut visible = text.len();
+        let image = stack
+            .iter()
+            .position(|open| open.marker.is_image())
+            .filter(|_| rules.links && !options.keep_image_alt);
+        let mut open = match image {
+            Some(index) => {
+                let start = stack[index].start;
+                cut(
+                    &mut edits,
+                    text,
+                    &mut visible,
+                    start,
+                    RepairEditKind::RemovedPartialImage,
+                );
+                &stack[..index]
+            }
+            None => stack,
+        };
+
+        // A citation key still being written is held back, with its bracket
+        // if nothing else is cited in it yet (`[@Coh`); outside brackets
+        // (`see @Coh`) only if asked, as `@name` may be a handle.
+        let in_text = self.fence.is_none()
+            && self.math.is_none()
+            && self.stack.last().is_none_or(|open| {
+                matches!(open.marker, Marker::LinkText { .. }) || open.marker.delimiter().is_some()
+            });
+        let bracket = open
+            .iter()
+            .rposition(|open| matches!(open.marker, Marker::LinkText { .. }));
+        let word_start = if visible == text.len() {
+            self.word_start
+        } else {
+            text[..visible]
+                .char_indices()
+                .rev()
+                .find(|(_, c)| !c.is_ascii_alphanumeric())
+                .map_or(0, |(i, c)| i + c.len_utf8())
+        };
+        let citation = partial_citation(&text[..visible], word_start)
+            .filter(|_| rules.citations && in_text)
+            .filter(|_| bracket.is_some() || (options.bare_citation_keys && options.partial_tokens));
+        if let Some(at) = citation {
+            let to = match bracket {
+                Some(index) if !has_citation(&text[open[index].content_start..at]) => {
+                    open[index].start
+                }
+                // `[see @Cohen63; @Smi` shows as `[see @Cohen63]`.
+                Some(_) => text[..at].trim_end_matches([' ', ';', ',']).len(),
+                None => at,
+            };
+            cut(
+                &mut edits,
+                text,
+                &mut visible,
+                to,
+                RepairEditKind::RemovedPartialCitation,
+            );
+            let kept = open.iter().take_while(|open| open.start < visible).count();
+            open = &open[..kept];
+        }
+
+        if image.is_none() {
+            // A code block closes on a line of its own, with a fence of the
+            // same character and length.
+            if let Some(fence) = self
+                .fence
+                .as_ref()
+                .filter(|fence| rules.code && in_scope(fence.start))
+            {
+                let closer = format!(
+                    "\n{}{}",
+                    " ".repeat(fence.indent),
+                    fence.marker.to_string().repeat(fence.len)
+                );
+                edits.push(RepairEdit::inserted(
+                    RepairEditKind::ClosedCodeBlock,
+                    closer,
+                ));
+            }
+            if rules.emphasis && !self.partial_closer.is_empty() {
+                let kind = if self.partial_closer.starts_with('~') {
+                    RepairEditKind::ClosedStrikethrough
+                } else {
+                    RepairEditKind::ClosedBold
+                };
+                edits.push(RepairEdit::inserted(kind, self.partial_closer.clone()));
+            }
+            if let Some(math) = self
+                .math
+                .as_ref()
+                .filter(|math| rules.math && in_scope(math.start))
+            {
+                let newline = if math.multiline { "\n" } else { "" };
+                let delimiter = if math.bracket { "\\]" } else { "$$" };
+                let closer = format!("{}{}", newline, delimiter);
+                edits.push(RepairEdit::inserted(
+                    RepairEditKind::ClosedDisplayMath,
+                    closer,
+                ));
+            }
+        }
+        let placeholder = &options.incomplete_link_url;
+        for open in open.iter().rev() {
+            let has_content =
+                self.last_content > open.content_start && open.content_start < visible;
+            let (kind, closer) = match open.marker {
+                // A bracketed citation, `[see @Cohen63`, is not a link.
+                Marker::LinkText { image: false }
+                    if rules.citations && has_citation(&text[open.content_start..visible]) =>
+                {
+                    (RepairEditKind::ClosedCitation, "]".to_string())
+                }
+                _ if !rules.links && open.marker.is_link() => continue,
+                Marker::LinkText { .. } => {
+                    (RepairEditKind::CompletedLink, format!("]({})", placeholder))
+                }
+                // A partial URL or label would lead nowhere: it is replaced
+                // by the placeholder.
+                Marker::LinkUrl { .. } => {
+                    let kind = RepairEditKind::RemovedPartialUrl;
+                    cut(&mut edits, text, &mut visible, open.content_start, kind);
+                    (RepairEditKind::CompletedLink, format!("{})", placeholder))
+                }
+                Marker::LinkRef { .. } => {
+                    let kind = RepairEditKind::RemovedPartialReference;
+                    cut(&mut edits, text, &mut visible, open.content_start - 1, kind);
+                    (RepairEditKind::CompletedLink, format!("({})", placeholder))
+                }
+                Marker::Autolink => (RepairEditKind::ClosedAutolink, ">".to_string()),
+                Marker::Code(len) if has_content && rules.code => {
+                    (RepairEditKind::ClosedCode, "`".repeat(len))
+                }
+                Marker::InlineMath { .. } if has_content && rules.math && options.partial_tokens => {
+                    (RepairEditKind::ClosedMath, "$".to_string())
+                }
+                Marker::LatexMath if has_content && rules.math => {
+                    (RepairEditKind::ClosedMath, "\\)".to_string())
+                }
+                Marker::Emphasis(c) if has_content && rules.emphasis => {
+                    (RepairEditKind::ClosedItalic, c.to_string())
+                }
+                Marker::Strong(c) if has_content && rules.emphasis => {
+                    (RepairEditKind::ClosedBold, c.to_string().repeat(2))
+                }
+                Marker::Strikethrough if has_content && rules.emphasis => {
+                    (RepairEditKind::ClosedStrikethrough, "~~".to_string())
+                }
+                _ => continue,
+            };
+            edits.push(RepairEdit::inserted(kind, closer));
+        }
+        if rules.tables
+            && options.partial_tokens
+            && self.fence.is_none()
+            && self.math.is_none()
+            && visible >= self.line_start
+        {
+            self.table_closers(text, &mut visible, &mut edits);
+        }
+        (visible, edits)
+    }
+
+    /// Inside a fenced code block, scans one whole line for the closing fence.
+    /// Only the start of a line can close the block: once a line being
+    /// written cannot, the rest of it is skipped as it arrives, rather than
+    /// scanned again from the start of the line.
+    fn scan_fenced_line(&mut self, text: &str, at_end: bool) -> Option<()> {
+        let rest = &text[self.pos..];
+        let fence = self.fence.as_mut()?;
+        if fence.plain_line {
+            let Some(newline) = rest.find('\n') else {
+                self.pos = text.len();
+                return Some(());
+            };
+            fence.plain_line = false;
+            self.pos += newline + 1;
+            self.line_start = self.pos;
+            return Some(());
+        }
+        let line_len = match rest.find('\n') {
+            Some(newline) => newline + 1,
+            None if at_end => rest.len(),
+            None => {
+                // A line that is neither blank nor a fence so far never will be.
+                let content = rest.trim_start_matches(' ');
+                let may_close = rest.len() - content.len() <= 3
+                    && content.trim_start_matches(fence.marker).trim().is_empty();
+                if may_close || rest.trim().is_empty() {
+                    return None;
+                }
+                fence.plain_line = true;
+                self.pos = text.len();
+                return Some(());
+            }
+        };
+        let line = rest[..line_len].trim_end();
+        let (marker, len) = fence_run(line);
+        let indent = line.len() - line.trim_start_matches(' ').len();
+        let after = line.trim_start().trim_start_matches(marker);
+        if indent <= 3 && marker == fence.marker && len >= fence.len && after.trim().is_empty() {
+            self.fence = None;
+            self.trailing = None;
+        }
+        self.pos += line_len;
+        self.line_start = self.pos;
+        if line.trim().is_empty() {
+            self.block_start = self.pos;
+            self.restart_trailing();
+        }
+        Some(())
+    }
+
+    /// At a blank line inside a code block or display math, starts scanning
+    /// the text after it on its own.
+    fn restart_trailing(&mut self) {
+        if self.trailing_block_only {
+            let start = self.block_start;
+            self.trailing = Some(Box::new(Scanner {
+                pos: start,
+                line_start: start,
+                last_content: start,
+                block_start: start,
+                counted: start,
+                word_start: start,
+                ..Scanner::default()
+            }));
+        }
+    }
+
+    /// At the start of a line, opens a fenced code block if the line starts
+    /// one. Returns `None` when the line is too short to tell yet.
+    fn open_fence(&mut self, text: &str, at_end: bool) -> Option<bool> {
+        let rest = &text[self.pos..];
+        let line_len = rest.find('\n').map_or(rest.len(), |newline| newline + 1);
+        let line = &rest[..line_len];
+        let (marker, len) = fence_run(line.trim_end_matches(['\n', '\r']));
+        let indent = line.len() - line.trim_start_matches(' ').len();
+        if indent == line.len() && indent <= 3 && !at_end {
+            // Only spaces so far: a fence could still follow.
+            return None;
+        }
+        if indent > 3 || (len == 0 && line.trim().is_empty()) {
+            return Some(false);
+        }
+        let run_end = indent + len;
+        // `` `` `` could still become a fence.
+        if run_end == line.len()
+            && !at_end
+            && len < 3
+            && line.trim_start().chars().all(|c| c == '`' || c == '~')
+        {
+            return None;
+        }
+        if len < 3 || (marker == '`' && line[run_end..].contains('`')) {
+            return Some(false);
+        }
+        if !line.ends_with('\n') && !at_end {
+            return None;
+        }
+
+        // A fence ends the paragraph and whatever was open in it.
+        self.stack.clear();
+        self.table = None;
+        self.header = None;
+        self.fence = Some(Fence {
+            start: self.pos,
+            marker,
+            len,
+            indent,
+            plain_line: false,
+        });
+        self.pos += line_len;
+        self.line_start = self.pos;
+        Some(true)
+    }
+
+    /// Inside display math, looks for the closing `$$` or `\]`.
+    fn scan_math(&mut self, text: &str, at_end: bool) -> Option<()> {
+        let rest = &text[self.pos..];
+        let c = rest.chars().next()?;
+        let bracket = self.math.as_ref().is_some_and(|math| math.bracket);
+        match c {
+            '$' if !bracket => {
+                let run = run_length(rest, '$');
+                if run == rest.len() && !at_end {
+                    return None;
+                }
+                if run >= 2 {
+                    self.math = None;
+                    self.trailing = None;
+                }
+                self.pos += run;
+            }
+            '\n' => {
+                if let Some(math) = self.math.as_mut() {
+                    math.multiline = true;
+                }
+                if text[self.line_start..self.pos].trim().is_empty() {
+                    self.block_start = self.pos + 1;
+                    self.restart_trailing();
+                }
+                self.pos += 1;
+                self.line_start = self.pos;
+            }
+            '\\' => {
+                let escaped = rest[1..].chars().next();
+                if escaped.is_none() && !at_end {
+                    return None;
+                }
+                if bracket && escaped == Some(']') {
+                    self.math = None;
+                    self.trailing = None;
+                }
+                self.pos += 1 + escaped.map_or(0, char::len_utf8);
+            }
+            _ => {
+                self.pos += c.len_utf8();
+                if !c.is_whitespace() {
+                    self.last_content = self.pos;
+                }
+            }
+        }
+        Some(())
+    }
+
+    /// Scans one character or delimiter run of paragraph text.
+    fn scan_inline(&mut self, text: &str, at_end: bool) -> Option<()> {
+        let rest = &text[self.pos..];
+        let c = rest.chars().next()?;
+        let top = self.stack.last().map(|open| open.marker);
+        let prev = text[..self.pos]
+            .chars()
+            .next_back()
+            .filter(|_| self.pos > self.line_start);
+
+        // In a table row, a pipe ends the cell and whatever was open in it.
+        if c == '|' && prev != Some('\\') && self.in_table_row(text) {
+            self.stack.clear();
+            self.advance(c);
+            return Some(());
+        }
+
+        if let Some(Marker::InlineMath { line_start }) = top {
+            match c {
+                '$' if prev.is_some_and(|prev| !prev.is_whitespace()) => {
+                    self.stack.pop();
+                    self.pos += 1;
+                }
+                '\\' => {
+                    let escaped = rest[1..].chars().next();
+                    if escaped.is_none() && !at_end {
+                        return None;
+                    }
+                    self.pos += 1 + escaped.map_or(0, char::len_utf8);
+                }
+                '\n' if text[self.line_start..self.pos].trim().is_empty() => {
+                    // The paragraph ended first: the `$` was plain text, and
+                    // what follows it is scanned again as such.
+                    let start = self.stack.pop().map_or(self.pos, |open| open.start);
+                    self.plain_opener = Some(start);
+                    self.pos = start;
+                    self.line_start = line_start;
+                }
+                // Math does not span table rows.
+                '\n' if self.in_table_row(text) => {
+                    self.stack.pop();
+                    self.newline(text);
+                }
+                '\n' => {
+                    self.pos += 1;
+                    self.line_start = self.pos;
+                }
+                _ => self.advance(c),
+            }
+            return Some(());
+        }
+
+        if let Some(Marker::LatexMath) = top {
+            match c {
+                '\\' => {
+                    let escaped = rest[1..].chars().next();
+                    if escaped.is_none() && !at_end {
+                        return None;
+                    }
+                    self.pos += 1 + escaped.map_or(0, char::len_utf8);
+                    if escaped == Some(')') {
+                        self.stack.pop();
+                    } else {
+                        self.last_content = self.pos;
+                    }
+                }
+                '\n' => self.newline(text),
+                _ => self.advance(c),
+            }
+            return Some(());
+        }
+
+        // Only the closing run ends a code span.
+        if let Some(Marker::Code(len)) = top {
+            match c {
+                '`' => {
+                    let run = run_length(rest, '`');
+                    if run == rest.len() && !at_end {
+                        return None;
+                    }
+                    if run == len {
+                        self.stack.pop();
+                    }
+                    self.pos += run;
+                }
+                '\n' => self.newline(text),
+                _ => self.advance(c),
+            }
+            return Some(());
+        }
+
+        if let Some(Marker::LinkRef { .. }) = top {
+            match c {
+                '\\' => {
+                    let escaped = rest[1..].chars().next();
+                    if escaped.is_none() && !at_end {
+                        return None;
+                    }
+                    self.pos += 1 + escaped.map_or(0, char::len_utf8);
+                    self.last_content = self.pos;
+                }
+                ']' => {
+                    self.stack.pop();
+                    self.advance(c);
+                }
+                '\n' => self.newline(text),
+                _ => self.advance(c),
+            }
+            return Some(());
+        }
+
+        if let Some(Marker::Autolink) = top {
+            match c {
+                '>' => {
+                    self.stack.pop();
+                    self.advance(c);
+                }
+                // Not a URL after all: what follows the `<` is scanned again
+                // as plain text.
+                '<' => self.unopen_autolink(),
+                _ if c.is_whitespace() => self.unopen_autolink(),
+                _ => self.advance(c),
+            }
+            return Some(());
+        }
+
+        if let Some(Marker::LinkUrl { depth, image }) = top {
+            match c {
+                '(' => self.set_top(Marker::LinkUrl {
+                    depth: depth + 1,
+                    image,
+                }),
+                ')' if depth > 0 => self.set_top(Marker::LinkUrl {
+                    depth: depth - 1,
+                    image,
+                }),
+                ')' => {
+                    self.stack.pop();
+                }
+                '\n' => {
+                    self.newline(text);
+                    return Some(());
+                }
+                _ => {}
+            }
+            self.advance(c);
+            return Some(());
+        }
+
+        match c {
+            '\\' => {
+                let escaped = rest[1..].chars().next();
+                if escaped.is_none() && !at_end {
+                    return None;
+                }
+                match escaped {
+                    Some('(') => self.push(Marker::LatexMath, 2),
+                    Some('[') => {
+                        self.math = Some(DisplayMath {
+                            start: self.pos,
+                            multiline: false,
+                            bracket: true,
+                        });
+                        self.pos += 2;
+                        self.table = None;
+                        self.header = None;
+                    }
+                    _ => {
+                        self.pos += 1 + escaped.map_or(0, char::len_utf8);
+                        self.last_content = self.pos;
+                    }
+                }
+            }
+            '\n' => self.newline(text),
+            '*' | '_' | '~' => {
+                let run = run_length(rest, c);
+                if run == rest.len() && !at_end {
+                    return None;
+                }
+                let next = rest[run..].chars().next();
+                self.delimiter_run(c, run, prev, next, at_end && run == rest.len());
+            }
+            '`' => {
+                let run = run_length(rest, '`');
+                if run == rest.len() && !at_end {
+                    return None;
+                }
+                self.push(Marker::Code(run), run);
+            }
+            '$' => {
+                let run = run_length(rest, '$');
+                if run == rest.len() && !at_end {
+                    return None;
+                }
+                let next = rest[run..].chars().next();
+                if run == 1 && self.plain_opener != Some(self.pos) && next.is_some_and(opens_math) {
+                    self.stack.push(Open {
+                        marker: Marker::InlineMath {
+                            line_start: self.line_start,
+                        },
+                        start: self.pos,
+                        content_start: self.pos + 1,
+                    });
+                }
+                self.pos += run;
+                if run == 2 {
+                    self.math = Some(DisplayMath {
+                        start: self.pos - run,
+                        multiline: false,
+                        bracket: false,
+                    });
+                    self.table = None;
+                    self.header = None;
+                } else {
+                    self.last_content = self.pos;
+                }
+            }
+            '!' => {
+                if rest.len() == 1 && !at_end {
+                    return None;
+                }
+                if rest[1..].starts_with('[') {
+                    self.push(Marker::LinkText { image: true }, 2);
+                } else {
+                    self.advance(c);
+                }
+            }
+            '[' => self.push(Marker::LinkText { image: false }, 1),
+            '<' => {
+                // `<https:` opens an autolink: a scheme of 2 to 32
+                // characters, starting with a letter, and a colon.
+                let after = &rest[1..];
+                let scheme = after.len()
+                    - after
+                        .trim_start_matches(|c: char| {
+                            c.is_ascii_alphanumeric() || matches!(c, '+' | '.' | '-')
+                        })
+                        .len();
+                let next = after[scheme..].chars().next();
+                if next.is_none() && scheme <= 32 && !at_end {
+                    return None;
+                }
+                if next == Some(':')
+                    && (2..=32).contains(&scheme)
+                    && after.starts_with(|c: char| c.is_ascii_alphabetic())
+                    && self.plain_opener != Some(self.pos)
+                {
+                    self.push(Marker::Autolink, 1);
+                } else {
+                    self.advance(c);
+                }
+            }
+            ']' => {
+                let Some(link) = self
+                    .stack
+                    .iter()
+                    .rposition(|open| matches!(open.marker, Marker::LinkText { .. }))
+                else {
+                    self.advance(c);
+                    return Some(());
+                };
+                if rest.len() == 1 && !at_end {
+                    return None;
+                }
+                // Whatever opened inside the link text and is still open is
+                // plain text.
+                let Open { marker, start, .. } = self.stack[link];
+                let image = marker.is_image();
+                self.stack.truncate(link);
+                self.pos += 1;
+                self.last_content = self.pos;
+                let marker = match rest[1..].chars().next() {
+                    Some('(') => Marker::LinkUrl { depth: 0, image },
+                    Some('[') => Marker::LinkRef { image },
+                    _ => return Some(()),
+                };
+                self.stack.push(Open {
+                    marker,
+                    start,
+                    content_start: self.pos + 1,
+                });
+                self.pos += 1;
+            }
+            _ => self.advance(c),
+        }
+        Some(())
+    }
+
+    /// A run of `*`, `_` or `~`, between `prev` and `next`.
+    fn delimiter_run(
+        &mut self,
+        c: char,
+        run: usize,
+        prev: Option<char>,
+        next: Option<char>,
+        ends_text: bool,
+    ) {
+        let left_flanking = next.is_some_and(|next| !next.is_whitespace());
+        let right_flanking = prev.is_some_and(|prev| !prev.is_whitespace());
+        let intraword =
+            prev.is_some_and(char::is_alphanumeric) && next.is_some_and(char::is_alphanumeric);
+        let (can_open, can_close) = match c {
+            '_' => (left_flanking && !intraword, right_flanking && !intraword),
+            _ => (left_flanking, right_flanking),
+        };
+
+        let mut remaining = run;
+        if can_close {
+            while remaining > 0 {
+                // The nearest opener of the same kind; openers above it stay
+                // unclosed, as plain text.
+                let Some(index) = self.stack.iter().rposition(|open| {
+                    open.marker
+                        .delimiter()
+                        .is_some_and(|(marker, _)| marker == c)
+                }) else {
+                    break;
+                };
+                if self.stack[index + 1..]
+                    .iter()
+                    .any(|open| open.marker.delimiter().is_none())
+                {
+                    break;
+                }
+                let (_, len) = self.stack[index].marker.delimiter().unwrap_or_default();
+                if remaining < len {
+                    // `**bold*` at the very end: the rest of the closer is
+                    // still to come.
+                    if ends_text {
+                        self.stack.truncate(index);
+                        self.partial_closer = c.to_string().repeat(len - remaining);
+                        remaining = 0;
+                    }
+                    break;
+                }
+                self.stack.truncate(index);
+                remaining -= len;
+            }
+        }
+
+        let start = self.pos + run - remaining;
+        self.pos += run;
+        if remaining > 0 && can_open {
+            let markers: &[Marker] = match (c, remaining) {
+                ('~', 2) => &[Marker::Strikethrough],
+                ('~', _) => &[],
+                (_, 1) => &[Marker::Emphasis(c)],
+                (_, 2) => &[Marker::Strong(c)],
+                (_, 3) => &[Marker::Strong(c), Marker::Emphasis(c)],
+                _ => &[],
+            };
+            for &marker in markers {
+                self.stack.push(Open {
+                    marker,
+                    start,
+                    content_start: self.pos,
+                });
+            }
+        }
+    }
+
+    fn push(&mut self, marker: Marker, len: usize) {
+        self.stack.push(Open {
+            marker,
+            start: self.pos,
+            content_start: self.pos + len,
+        });
+        self.pos += len;
+    }
+
+    /// Drops the autolink on top of the stack, to scan its text again from
+    /// just after the `<`.
+    fn unopen_autolink(&mut self) {
+        if let Some(open) = self.stack.pop() {
+            self.plain_opener = Some(open.start);
+            self.pos = open.start;
+        }
+    }
+
+    fn set_top(&mut self, marker: Marker) {
+        if let Some(open) = self.stack.last_mut() {
+            open.marker = marker;
+        }
+    }
+
+    fn advance(&mut self, c: char) {
+        self.pos += c.len_utf8();
+        if !c.is_whitespace() {
+            self.last_content = self.pos;
+        }
+    }
+
+    /// A line break; a blank line ends the paragraph and everything open in
+    /// it, and the end of a table row whatever was open in the row.
+    fn newline(&mut self, text: &str) {
+        let line = &text[self.line_start..self.pos];
+        let header = self.header.take();
+        if line.trim().is_empty() {
+            self.stack.clear();
+            self.table = None;
+            self.block_start = self.pos + 1;
+        } else if self.table.is_some() {
+            self.stack.clear();
+        } else if header.is_some_and(|cells| is_delimiter_row(line) && table_cells(line) == cells) {
+            self.stack.clear();
+            self.table = header;
+        } else if is_header_row(line) {
+            self.stack.clear();
+            self.header = Some(table_cells(line));
+        }
+        self.pos += 1;
+        self.line_start = self.pos;
+    }
+
+    /// Whether the line being scanned is, or could become, a table row.
+    fn in_table_row(&self, text: &str) -> bool {
+        self.table.is_some() || text[self.line_start..].trim_start().starts_with('|')
+    }
+
+    /// What the last line, from `line_start` to `visible`, needs to be shown
+    /// as part of a table: the cells or delimiter row to append, or a cut.
+    fn table_closers(&self, text: &str, visible: &mut usize, edits: &mut Vec<RepairEdit>) {
+        let line = &text[self.line_start..*visible];
+        let trimmed = line.trim_end();
+        let leading_pipe = trimmed.trim_start().starts_with('|');
+        if self.table.is_none() && self.header.is_none() && !leading_pipe {
+            return;
+        }
+        let closed = trimmed.ends_with('|') && !trimmed.ends_with("\\|");
+        // The cells of the line, as `table_cells` counts them; what is cut
+        // from the end of the line holds no pipe.
+        let cells = if trimmed.trim_start().len() <= usize::from(leading_pipe) {
+            0
+        } else {
+            self.row.pipes - usize::from(leading_pipe) + usize::from(!closed)
+        };
+        if let Some(columns) = self.table {
+            // A row cut short gets empty cells up to the column count.
+            let mut row = String::new();
+            if !closed {
+                row.push_str(" |");
+            }
+            for _ in cells..columns {
+                row.push_str(" |");
+            }
+            if !trimmed.is_empty() && !row.is_empty() {
+                edits.push(RepairEdit::inserted(RepairEditKind::CompletedTableRow, row));
+            }
+            return;
+        }
+        let delimiter = |columns| {
+            RepairEdit::inserted(
+                RepairEditKind::AddedDelimiterRow,
+                format!("\n{}", delimiter_row(columns)),
+            )
+        };
+        if let Some(columns) = self.header {
+            // A delimiter row being written is replaced by a whole one.
+            let delimiter_chars = self
+                .row
+                .not_delimiter
+                .is_none_or(|at| at >= self.line_start + trimmed.len());
+            let partial = !trimmed.is_empty()
+                && delimiter_chars
+                && !(cells == columns && is_delimiter_row(trimmed));
+            if partial {
+                // With its line break, which the whole row brings back.
+                let kind = RepairEditKind::RemovedPartialDelimiterRow;
+                cut(edits, text, visible, self.line_start - 1, kind);
+            }
+            if trimmed.is_empty() || partial {
+                edits.push(delimiter(columns));
+            }
+            return;
+        }
+        if cells == 0 {
+            // A lone `|`: nothing to show yet.
+            let kind = RepairEditKind::RemovedPartialTableRow;
+            cut(edits, text, visible, self.line_start, kind);
+            return;
+        }
+        // A whole header row gets its delimiter row. A row still being
+        // written is left alone: `|x| is the absolute value` is prose.
+        if is_header_row(trimmed) {
+            edits.push(delimiter(cells));
+        }
+    }
+}
+
+/// The offset of the `@` of a citation key cut off at the very end of
+/// `text`, which could still become a short key (`@[A-Za-z]+\d{2}[a-z]?`):
+/// one is not complete before the two digits of its year (`@Coh`,
+/// `@Cohen6`), while `@Cohen63` already cites something. `word_start` is
+/// where the run of ASCII letters and digits `text` ends with starts.
+fn partial_citation(text: &str, word_start: usize) -> Option<usize> {
+    let at = text[..word_start].strip_suffix('@')?.len();
+    let word = &text[word_start..];
+    let letters = word.trim_end_matches(|c: char| c.is_ascii_digit());
+    let digits = word.len() - letters.len();
+    if digits > 1
+        || (digits == 1 && letters.is_empty())
+        || !letters.bytes().all(|b| b.is_ascii_alphabetic())
+    {
+        return None;
+    }
+    let prev = text[..at].chars().next_back();
+    (!prev.is_some_and(|prev| prev.is_alphanumeric() || prev == '\\')).then_some(at)
+}
+
+/// Whether `text` cites a key, as `@` followed by a letter (not in an email
+/// address).
+fn has_citation(text: &str) -> bool {
+    text.match_indices('@').any(|(at, _)| {
+        let prev = text[..at].chars().next_back();
+        let next = text[at + 1..].chars().next();
+        !prev.is_some_and(|prev| prev.is_alphanumeric() || prev == '\\')
+            && next.is_some_and(|next| next.is_ascii_alphabetic())
+    })
+}
+
+/// Cuts the text shown back to `to`, recording what is held back.
+fn cut(
+    edits: &mut Vec<RepairEdit>,
+    text: &str,
+    visible: &mut usize,
+    to: usize,
+    kind: RepairEditKind,
+) {
+    if to < *visible {
+        edits.push(RepairEdit {
+            kind,
+            position: to,
+            inserted: String::new(),
+            removed: text[to..*visible].to_string(),
+        });
+        *visible = to;
+    }
+}
+
+/// Whether a whole line could be the header row of a table: GFM does not
+/// require the outer pipes, but taking only rows with both keeps prose such
+/// as `|x| is the absolute value` out of tables.
+fn is_header_row(line: &str) -> bool {
+    let line = line.trim();
+    line.len() > 1 && line.starts_with('|') && line.ends_with('|') && !line.ends_with("\\|")
+}
+
+/// Whether a line is a table delimiter row, such as `| :-- | --: |`.
+fn is_delimiter_row(line: &str) -> bool {
+    let line = line.trim();
+    let line = line.strip_prefix('|').unwrap_or(line);
+    let line = line.strip_suffix('|').unwrap_or(line);
+    line.contains('-')
+        && line.split('|').all(|cell| {
+            let cell = cell.trim();
+            let cell = cell.strip_prefix(':').unwrap_or(cell);
+            let cell = cell.strip_suffix(':').unwrap_or(cell);
+            !cell.is_empty() && cell.chars().all(|c| c == '-')
+        })
+}
+
+/// The number of cells in a table row, counting a last cell without its
+/// closing pipe.
+fn table_cells(line: &str) -> usize {
+    let line = line.trim();
+    let line = line.strip_prefix('|').unwrap_or(line);
+    if line.is_empty() {
+        return 0;
+    }
+    let mut pipes = 0;
+    let mut escaped = false;
+    for c in line.chars() {
+        if c == '|' && !escaped {
+            pipes += 1;
+        }
+        escaped = c == '\\' && !escaped;
+    }
+    if line.ends_with('|') && !line.ends_with("\\|") {
+        pipes
+    } else {
+        pipes + 1
+    }
+}
+
+/// A delimiter row for `columns` columns.
+fn delimiter_row(columns: usize) -> String {
+    let mut row = "|".to_string();
+    for _ in 0..columns {
+        row.push_str(" --- |");
+    }
+    row
+}
+
+/// Whether a `$` followed by `next` opens inline math: not before a space,
+/// nor before a digit, which makes it a price (`$5 and $10`).
+fn opens_math(next: char) -> bool {
+    !next.is_whitespace() && !next.is_ascii_digit()
+}
+
+/// Number of leading `c`s.
+fn run_length(text: &str, c: char) -> usize {
+    text.len() - text.trim_start_matches(c).len()
+}
+
+/// The fence character and length a line starts with, after up to three
+/// spaces; `(' ', 0)` when it starts with neither.
+fn fence_run(line: &str) -> (char, usize) {
+    let line = line.trim_start_matches(' ');
+    match line.chars().next() {
+        Some(c @ ('`' | '~')) => (c, run_length(line, c)),
+        _ => (' ', 0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::repair::{
+        repair_markdown, repair_markdown_with_edits, RepairEditKind, RepairOptions, RepairRules,
+        StreamingRepair,
+    };
+
+    const NESTING: &[(&str, &str)] = &[
+        ("Some **bold", "Some **bold**"),
+        ("Some *italic", "Some *italic*"),
+        ("__under", "__under__"),
+        ("***both", "***both***"),
+        ("~~strike **bold", "~~strike **bold**~~"),
+        ("**bold *and italic", "**bold *and italic***"),
+        ("**bold*", "**bold**"),
+        ("*a\n", "*a*\n"),
+        ("*a\n\nb", "*a\n\nb"),
+        ("snake_case_word", "snake_case_word"),
+        ("* list item", "* list item"),
+        ("Some **", "Some **"),
+    ];
+
+    const CODE: &[(&str, &str)] = &[
+        ("`code", "`code`"),
+        ("`code with **stars", "`code with **stars`"),
+        ("``code ` tick", "``code ` tick``"),
+        ("```rust\nfn main() {", "```rust\nfn main() {\n```"),
+        ("~~~\ncode", "~~~\ncode\n~~~"),
+        ("  ```\nindented", "  ```\nindented\n  ```"),
+        ("````\n```\nstill code", "````\n```\nstill code\n````"),
+        ("```\ndone\n```\n*a", "```\ndone\n```\n*a*"),
+        // Only a line that is a fence from its start closes the block.
+        ("```\n{\"a\": \"```\"}\n*b", "```\n{\"a\": \"```\"}\n*b\n```"),
+        ("```\n    ```\n*b", "```\n    ```\n*b\n```"),
+        ("```\n   ```  \n*b", "```\n   ```  \n*b*"),
+        ("```\n```` x\n*b", "```\n```` x\n*b\n```"),
+    ];
+
+    const MATH: &[(&str, &str)] = &[
+        ("$x^2", "$x^2$"),
+        ("$$x^2", "$$x^2$$"),
+        ("$$\nx^2", "$$\nx^2\n$$"),
+        (r"\(x", r"\(x\)"),
+        (r"\[x", r"\[x\]"),
+        ("$x_1 *y", "$x_1 *y$"),
+        // A `$` before a digit or a space is a price.
+        ("$5 and $10", "$5 and $10"),
+        ("costs $5", "costs $5"),
+        ("from $ to *b", "from $ to *b*"),
+        ("$x\n\n*b", "$x\n\n*b*"),
+    ];
+
+    const LINKS: &[(&str, &str)] = &[
+        ("[docs", "[docs](streamdown:incomplete-link)"),
+        ("[docs](https://exa", "[docs](streamdown:incomplete-link)"),
+        ("See [link](a(b", "See [link](streamdown:incomplete-link)"),
+        ("[docs][re", "[docs](streamdown:incomplete-link)"),
+        ("[docs](https://example.com) and *more", "[docs](https://example.com) and *more*"),
+        ("[a **bold", "[a **bold**](streamdown:incomplete-link)"),
+        ("<https://exa", "<https://exa>"),
+        ("a < b", "a < b"),
+        ("<b>not a link", "<b>not a link"),
+    ];
+
+    const IMAGES: &[(&str, &str)] = &[
+        ("text ![alt", "text "),
+        ("![alt](img.pn", ""),
+        ("![alt](img.png) and *a", "![alt](img.png) and *a*"),
+        ("!not an image", "!not an image"),
+        // The word before the image ends with a multi-byte character.
+        ("naïve![alt", "naïve"),
+        ("éCoh![", "éCoh"),
+    ];
+
+    const CITATIONS: &[(&str, &str)] = &[
+        ("[see @Cohen63", "[see @Cohen63]"),
+        ("As shown in [@Cohe", "As shown in "),
+        ("As shown in [see @Cohen63; @Smi", "As shown in [see @Cohen63]"),
+        ("As shown in [@", "As shown in "),
+        ("mail foo@bar.com and [x", "mail foo@bar.com and [x](streamdown:incomplete-link)"),
+        // Outside brackets, `@name` may be a handle.
+        ("Follow us @rustlang", "Follow us @rustlang"),
+        ("As shown by @Coh", "As shown by @Coh"),
+        ("Voilà [@Coh", "Voilà "),
+    ];
+
+    const TABLES: &[(&str, &str)] = &[
+        ("| a | b |", "| a | b |\n| --- | --- |"),
+        ("| a | b |\n|---|---|\n| 1", "| a | b |\n|---|---|\n| 1 | |"),
+        ("| a | b |\n|---|---|\n| `x | **b", "| a | b |\n|---|---|\n| `x | **b** |"),
+        ("| a | b |\n| :-", "| a | b |\n| --- | --- |"),
+        ("| a | b |\n|---|---|\n| 1 | 2 |", "| a | b |\n|---|---|\n| 1 | 2 |"),
+        ("| a \\| b |\n|---|\n| 1", "| a \\| b |\n|---|\n| 1 |"),
+        // Prose starting with a pipe is not a header row being written.
+        ("|x| is the absolute value", "|x| is the absolute value"),
+        ("Define\n|x| is *important", "Define\n|x| is *important*"),
+    ];
+
+    const TRAILING_BLOCK_ONLY: &[(&str, &str)] = &[
+        ("*open\n\nnew **b", "*open\n\nnew **b**"),
+        ("*open and **b", "*open and **b***"),
+        // A construct left open in an earlier block is plain text.
+        ("Start $$ here\n\nlater *text", "Start $$ here\n\nlater *text*"),
+        ("```\ncode\n\nlater **bold", "```\ncode\n\nlater **bold**"),
+        ("```\ncode\n\n*b\n```\n", "```\ncode\n\n*b\n```\n"),
+    ];
+
+    fn trailing_block_only() -> RepairOptions {
+        RepairOptions { trailing_block_only: true, ..RepairOptions::default() }
+    }
+
+    fn assert_repairs(cases: &[(&str, &str)], options: &RepairOptions) {
+        for &(input, expected) in cases {
+            assert_eq!(repair_markdown(input, options), expected, "input: {:?}", input);
+        }
+    }
+
+    #[test]
+    fn nesting() {
+        assert_repairs(NESTING, &RepairOptions::default());
+    }
+
+    #[test]
+    fn code() {
+        assert_repairs(CODE, &RepairOptions::default());
+    }
+
+    #[test]
+    fn math() {
+        assert_repairs(MATH, &RepairOptions::default());
+    }
+
+    #[test]
+    fn links() {
+        assert_repairs(LINKS, &RepairOptions::default());
+        let options = RepairOptions { incomplete_link_url: "#".to_string(), ..RepairOptions::default() };
+        assert_eq!(repair_markdown("[docs](https://exa", &options), "[docs](#)");
+    }
+
+    #[test]
+    fn images() {
+        assert_repairs(IMAGES, &RepairOptions::default());
+        let options = RepairOptions { keep_image_alt: true, ..RepairOptions::default() };
+        assert_eq!(repair_markdown("text ![alt", &options), "text ![alt](streamdown:incomplete-link)");
+    }
+
+    #[test]
+    fn citations() {
+        assert_repairs(CITATIONS, &RepairOptions::default());
+        let options = RepairOptions { bare_citation_keys: true, ..RepairOptions::default() };
+        assert_repairs(
+            &[
+                ("As shown by @Coh", "As shown by "),
+                ("As shown by @Cohen6", "As shown by "),
+                ("As shown by @Cohen63", "As shown by @Cohen63"),
+                ("Mail foo@bar", "Mail foo@bar"),
+            ],
+            &options,
+        );
+    }
+
+    #[test]
+    fn finished_text_keeps_partial_tokens() {
+        let options = RepairOptions { bare_citation_keys: true, partial_tokens: false, ..RepairOptions::default() };
+        assert_repairs(
+            &[
+                ("Maintained by @leonardopedro", "Maintained by @leonardopedro"),
+                ("Costs $x", "Costs $x"),
+                ("| a | b |", "| a | b |"),
+                ("| a | b |\n| --- | --- |\n| 1", "| a | b |\n| --- | --- |\n| 1"),
+                // Other constructs are still closed.
+                ("Some **bold", "Some **bold**"),
+                ("See [@Coh", "See "),
+            ],
+            &options,
+        );
+    }
+
+    #[test]
+    fn tables() {
+        assert_repairs(TABLES, &RepairOptions::default());
+    }
+
+    #[test]
+    fn rules() {
+        let input = "**bold `code [link $x";
+        let only = |rules: &str| RepairOptions { rules: rules.parse().unwrap(), ..RepairOptions::default() };
+        assert_eq!(repair_markdown(input, &only("none")), input);
+        assert_eq!(repair_markdown(input, &only("emphasis")), "**bold `code [link $x**");
+        assert_eq!(repair_markdown(input, &only("code")), "**bold `code [link $x`");
+        assert_eq!(repair_markdown("**bold [link", &only("links")), "**bold [link](streamdown:incomplete-link)");
+        assert_eq!(repair_markdown("a $x", &only("math,emphasis")), "a $x$");
+        assert_eq!(repair_markdown("| a | b |", &only("emphasis")), "| a | b |");
+        assert_eq!(repair_markdown("[@Coh", &only("links")), "[@Coh](streamdown:incomplete-link)");
+        assert!("emphasis,bogus".parse::<RepairRules>().is_err());
+    }
+
+    #[test]
+    fn trailing_block() {
+        assert_repairs(TRAILING_BLOCK_ONLY, &trailing_block_only());
+    }
+
+    #[test]
+    fn streaming_matches_repair_markdown() {
+        let default = RepairOptions::default();
+        let trailing = trailing_block_only();
+        let cases = [NESTING, CODE, MATH, LINKS, IMAGES, CITATIONS, TABLES]
+            .into_iter()
+            .map(|cases| (cases, &default))
+            .chain([(TRAILING_BLOCK_ONLY, &trailing)]);
+        for (cases, options) in cases {
+            for &(input, _) in cases {
+                let mut repair = StreamingRepair::new(options.clone());
+                for (i, c) in input.char_indices() {
+                    let streamed = repair.push(&c.to_string()).to_string();
+                    let prefix = &input[..i + c.len_utf8()];
+                    assert_eq!(streamed, repair_markdown(prefix, options), "prefix: {:?}", prefix);
+                }
+            }
+        }
+    }
+
+    /// Checks the edits made to `input`, as (kind, position, inserted,
+    /// removed), and that they map back into it: removed text is found at its
+    /// position, and the insertions, at the end of what is kept, give the
+    /// repaired text.
+    fn assert_edits(input: &str, options: &RepairOptions, expected: &[(RepairEditKind, usize, &str, &str)]) {
+        let (repaired, edits) = repair_markdown_with_edits(input, options);
+        let found: Vec<_> = edits
+            .iter()
+            .map(|edit| (edit.kind, edit.position, edit.inserted.as_str(), edit.removed.as_str()))
+            .collect();
+        assert_eq!(found, expected, "input: {:?}", input);
+
+        let mut kept = input.len();
+        for edit in edits.iter().filter(|edit| !edit.removed.is_empty()) {
+            assert_eq!(&input[edit.position..edit.position + edit.removed.len()], edit.removed);
+            kept = kept.min(edit.position);
+        }
+        let body = input[..kept].trim_end();
+        let mut rebuilt = body.to_string();
+        for edit in edits.iter().filter(|edit| !edit.inserted.is_empty()) {
+            assert_eq!(edit.position, body.len(), "input: {:?}", input);
+            rebuilt.push_str(&edit.inserted);
+        }
+        rebuilt.push_str(&input[body.len()..kept]);
+        assert_eq!(rebuilt, repaired, "input: {:?}", input);
+    }
+
+    #[test]
+    fn edits_map_back_into_the_input() {
+        use RepairEditKind::*;
+        let default = RepairOptions::default();
+        let placeholder = "streamdown:incomplete-link)";
+        assert_edits(
+            "See [docs](https://exa",
+            &default,
+            &[(RemovedPartialUrl, 11, "", "https://exa"), (CompletedLink, 11, placeholder, "")],
+        );
+        assert_edits(
+            "text ![alt](ima and more",
+            &default,
+            &[(RemovedPartialImage, 5, "", "![alt](ima and more")],
+        );
+        assert_edits("Some **bold  \n", &default, &[(ClosedBold, 11, "**", "")]);
+        assert_edits(
+            "*a **b",
+            &default,
+            &[(ClosedBold, 6, "**", ""), (ClosedItalic, 6, "*", "")],
+        );
+        assert_edits("See [@Coh", &default, &[(RemovedPartialCitation, 4, "", "[@Coh")]);
+        let bare = RepairOptions { bare_citation_keys: true, ..RepairOptions::default() };
+        assert_edits("As shown by @Cohen6", &bare, &[(RemovedPartialCitation, 12, "", "@Cohen6")]);
+        assert_edits("| a | b |", &default, &[(AddedDelimiterRow, 9, "\n| --- | --- |", "")]);
+        assert_edits(
+            "| a | b |\n| -",
+            &default,
+            &[
+                (RemovedPartialDelimiterRow, 9, "", "\n| -"),
+                (AddedDelimiterRow, 9, "\n| --- | --- |", ""),
+            ],
+        );
+        assert_edits(
+            "| a | b |\n|---|---|\n| 1",
+            &default,
+            &[(CompletedTableRow, 23, " | |", "")],
+        );
+    }
+
+    #[test]
+    fn complete_text_has_no_edits() {
+        let options = RepairOptions::default();
+        for input in [
+            "| a | b |\n|---|---|\n| 1 | 2 |",
+            "Some **bold** and `code`.\n\n$$\nx\n$$\n",
+            "A [link](https://example.com) and [@Cohen63].",
+        ] {
+            let (repaired, edits) = repair_markdown_with_edits(input, &options);
+            assert_eq!(repaired, input);
+            assert!(edits.is_empty(), "input: {:?}, edits: {:?}", input, edits);
+        }
+    }
+}
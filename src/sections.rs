@@ -0,0 +1,298 @@
+//! Splits a document into reference sections and locates the bibliographies
+//! printed for each of them.
+//!
+//! A reference section starts at the top of the document, at every
+//! `<!-- refsection -->` marker and, when a scope level is set, at every ATX
+//! heading of that level or above. Bibliographies are requested with
+//! `<!-- bibliography ... -->` markers, which accept biblatex-style filters:
+//!
+//! ```markdown
+//! <!-- bibliography type=book,collection keyword=primary title="Primary sources" -->
+//! <!-- bibliography notkeyword=primary field.journal="Nature" -->
+//! ```
+//!
+//! Entries can be listed without being cited with a `<!-- nocite ... -->`
+//! directive, the equivalent of `\nocite`. It takes Markdown or BibTeX keys,
+//! `*` for every entry, or the same filters as a bibliography marker:
+//!
+//! ```markdown
+//! <!-- nocite @Cohen63 CitekeyBook keyword=background -->
+//! ```
+
+use crate::import::SourceRecord;
+use hayagriva::Entry;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::collections::HashMap;
+use std::ops::Range;
+
+static BIBLIOGRAPHY_MARKER_PATTERN: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^\s*<!--\s*bibliography(?:\s(.*?))?-->\s*$").unwrap());
+static NOCITE_DIRECTIVE_PATTERN: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^\s*<!--\s*nocite(?:[:\s](.*?))?-->\s*$").unwrap());
+static REFSECTION_MARKER_PATTERN: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^\s*<!--\s*refsection\s*-->\s*$").unwrap());
+static ATX_HEADING_PATTERN: Lazy<Regex> = Lazy::new(|| Regex::new(r"^ {0,3}(#{1,6})(?:\s|$)").unwrap());
+static FENCE_PATTERN: Lazy<Regex> = Lazy::new(|| Regex::new(r"^ {0,3}(`{3,}|~{3,})").unwrap());
+static MARKER_ATTRIBUTE_PATTERN: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"([\w.-]+)=(?:"([^"]*)"|(\S+))"#).unwrap());
+
+/// The reference sections of a document and the bibliographies printed for them.
+pub(crate) struct Layout {
+    /// Byte offset at which each reference section starts; the first is always 0.
+    pub section_starts: Vec<usize>,
+    /// Bibliographies in document order.
+    pub blocks: Vec<Block>,
+    /// `nocite` directives in document order.
+    pub nocites: Vec<Nocite>,
+}
+
+/// A bibliography printed for one reference section.
+pub(crate) struct Block {
+    pub section: usize,
+    pub filter: EntryFilter,
+    pub title: Option<String>,
+    /// Byte range of the marker line, or `None` when the bibliography is
+    /// appended at the end of its section.
+    pub marker: Option<Range<usize>>,
+}
+
+/// Entries listed in a section's bibliographies without being cited.
+pub(crate) struct Nocite {
+    pub section: usize,
+    /// Markdown (`@Cohen63`) or BibTeX keys, without the `@`.
+    pub keys: Vec<String>,
+    /// Selects every entry it matches; `*` is an empty filter.
+    pub filter: Option<EntryFilter>,
+    /// Byte range of the directive line including its line break, or `None`
+    /// when it was not written in the document.
+    pub directive: Option<Range<usize>>,
+}
+
+impl Nocite {
+    /// Parses a directive or option such as `@Cohen63, CitekeyBook keyword=primary`.
+    pub fn parse(spec: &str, section: usize, directive: Option<Range<usize>>) -> Self {
+        let filter_spec: Vec<&str> = MARKER_ATTRIBUTE_PATTERN
+            .find_iter(spec)
+            .map(|m| m.as_str())
+            .collect();
+        let rest = MARKER_ATTRIBUTE_PATTERN.replace_all(spec, " ");
+        let mut keys = Vec::new();
+        let mut all = false;
+        for token in rest.split(|c: char| c.is_whitespace() || c == ',' || c == ';') {
+            match token.trim_start_matches('@') {
+                "" => {}
+                "*" => all = true,
+                key => keys.push(key.to_string()),
+            }
+        }
+
+        let filter = (all || !filter_spec.is_empty()).then(|| parse_marker(&filter_spec.join(" ")).0);
+        Nocite { section, keys, filter, directive }
+    }
+}
+
+/// A range of the document that is not copied as-is into the output.
+pub(crate) enum Cut {
+    /// The marker of the bibliography with this index in [`Layout::blocks`].
+    Block(usize),
+    /// A `nocite` directive, which is dropped from the output.
+    Nocite,
+}
+
+impl Layout {
+    /// Whether the document uses neither sections nor markers, in which case a
+    /// single bibliography is printed even when nothing was cited.
+    pub fn is_single(&self) -> bool {
+        self.section_starts.len() == 1 && self.blocks.len() == 1 && self.blocks[0].marker.is_none()
+    }
+
+    /// Markers and directives of a section, ordered by position.
+    pub fn cuts(&self, section: usize) -> Vec<(Range<usize>, Cut)> {
+        let mut cuts: Vec<(Range<usize>, Cut)> = self
+            .blocks
+            .iter()
+            .enumerate()
+            .filter(|(_, block)| block.section == section)
+            .filter_map(|(index, block)| Some((block.marker.clone()?, Cut::Block(index))))
+            .chain(
+                self.nocites
+                    .iter()
+                    .filter(|nocite| nocite.section == section)
+                    .filter_map(|nocite| Some((nocite.directive.clone()?, Cut::Nocite))),
+            )
+            .collect();
+        cuts.sort_by_key(|(range, _)| range.start);
+        cuts
+    }
+
+    /// The bibliography appended at the end of a section, if it has no marker.
+    pub fn appended_block(&self, section: usize) -> Option<usize> {
+        self.blocks
+            .iter()
+            .position(|block| block.section == section && block.marker.is_none())
+    }
+
+    /// Byte range of a reference section within a document of length `len`.
+    pub fn section_range(&self, section: usize, len: usize) -> Range<usize> {
+        let end = self.section_starts.get(section + 1).copied().unwrap_or(len);
+        self.section_starts[section]..end
+    }
+}
+
+/// Restricts the entries printed by a bibliography, after biblatex's
+/// `\printbibliography` options. An empty filter accepts everything.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct EntryFilter {
+    pub types: Vec<String>,
+    pub not_types: Vec<String>,
+    pub keywords: Vec<String>,
+    pub not_keywords: Vec<String>,
+    /// Field name and required value; `*` only requires the field to be present.
+    pub fields: Vec<(String, String)>,
+}
+
+impl EntryFilter {
+    /// Checks an entry, preferring the type and fields of its source file
+    /// (e.g. the BibTeX entry type) when available.
+    pub fn matches(&self, entry: &Entry, record: Option<&SourceRecord>) -> bool {
+        let entry_type = match record {
+            Some(record) => record.entry_type.to_lowercase(),
+            None => format!("{:?}", entry.entry_type()).to_lowercase(),
+        };
+        let empty = HashMap::new();
+        let fields = record.map_or(&empty, |record| &record.fields);
+        // BibTeX calls the field `keywords`, CSL-JSON `keyword`.
+        let keywords: Vec<String> = fields
+            .get("keywords")
+            .or_else(|| fields.get("keyword"))
+            .map(|k| {
+                k.split([',', ';'])
+                    .map(|k| k.trim().to_lowercase())
+                    .filter(|k| !k.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        (self.types.is_empty() || self.types.contains(&entry_type))
+            && !self.not_types.contains(&entry_type)
+            && self.keywords.iter().all(|k| keywords.contains(k))
+            && !self.not_keywords.iter().any(|k| keywords.contains(k))
+            && self.fields.iter().all(|(name, value)| {
+                fields
+                    .get(name)
+                    .is_some_and(|v| value == "*" || v.eq_ignore_ascii_case(value))
+            })
+    }
+}
+
+/// Parses the attributes of a `<!-- bibliography ... -->` marker.
+fn parse_marker(attributes: &str) -> (EntryFilter, Option<String>) {
+    let mut filter = EntryFilter::default();
+    let mut title = None;
+    let list = |value: &str| -> Vec<String> {
+        value
+            .split(',')
+            .map(|v| v.trim().to_lowercase())
+            .filter(|v| !v.is_empty())
+            .collect()
+    };
+
+    for cap in MARKER_ATTRIBUTE_PATTERN.captures_iter(attributes) {
+        let name = cap[1].to_lowercase();
+        let value = cap.get(2).or(cap.get(3)).map_or("", |m| m.as_str());
+        match name.as_str() {
+            "type" => filter.types.extend(list(value)),
+            "nottype" => filter.not_types.extend(list(value)),
+            "keyword" => filter.keywords.extend(list(value)),
+            "notkeyword" => filter.not_keywords.extend(list(value)),
+            "title" => title = Some(value.to_string()),
+            _ => {
+                if let Some(field) = name.strip_prefix("field.") {
+                    filter.fields.push((field.to_string(), value.to_string()));
+                }
+            }
+        }
+    }
+
+    (filter, title)
+}
+
+/// Finds the reference sections and bibliography markers of a document.
+///
+/// Headings of level `scope_level` or above start a new section. Markers and
+/// headings inside fenced code blocks are ignored. Every section without an
+/// explicit bibliography marker gets one appended at its end.
+pub(crate) fn scan(markdown: &str, scope_level: Option<usize>) -> Layout {
+    let mut section_starts = vec![0];
+    let mut markers: Vec<Block> = Vec::new();
+    let mut nocites: Vec<Nocite> = Vec::new();
+    let mut open_fence: Option<String> = None;
+    let mut offset = 0;
+
+    for line in markdown.split_inclusive('\n') {
+        let line_start = offset;
+        offset += line.len();
+        let content = line.trim_end_matches(['\n', '\r']);
+
+        if let Some(cap) = FENCE_PATTERN.captures(content) {
+            let fence = &cap[1];
+            match &open_fence {
+                Some(open) if fence.starts_with(open.as_str()) && content.trim().len() == fence.len() => {
+                    open_fence = None;
+                }
+                Some(_) => {}
+                None => open_fence = Some(fence.to_string()),
+            }
+            continue;
+        }
+        if open_fence.is_some() {
+            continue;
+        }
+
+        let starts_section = REFSECTION_MARKER_PATTERN.is_match(content)
+            || ATX_HEADING_PATTERN
+                .captures(content)
+                .zip(scope_level)
+                .is_some_and(|(cap, level)| cap[1].len() <= level);
+        if starts_section && line_start > *section_starts.last().unwrap() {
+            section_starts.push(line_start);
+        }
+
+        if let Some(cap) = NOCITE_DIRECTIVE_PATTERN.captures(content) {
+            let section = section_starts.len() - 1;
+            let directive = cap.get(1).map_or("", |m| m.as_str());
+            nocites.push(Nocite::parse(directive, section, Some(line_start..offset)));
+        }
+
+        if let Some(cap) = BIBLIOGRAPHY_MARKER_PATTERN.captures(content) {
+            let (filter, title) = parse_marker(cap.get(1).map_or("", |m| m.as_str()));
+            markers.push(Block {
+                section: section_starts.len() - 1,
+                filter,
+                title,
+                marker: Some(line_start..line_start + content.len()),
+            });
+        }
+    }
+
+    let mut blocks = Vec::new();
+    let mut markers = markers.into_iter().peekable();
+    for section in 0..section_starts.len() {
+        let mut has_marker = false;
+        while let Some(block) = markers.next_if(|b| b.section == section) {
+            has_marker = true;
+            blocks.push(block);
+        }
+        if !has_marker {
+            blocks.push(Block {
+                section,
+                filter: EntryFilter::default(),
+                title: None,
+                marker: None,
+            });
+        }
+    }
+
+    Layout { section_starts, blocks, nocites }
+}
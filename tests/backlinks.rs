@@ -0,0 +1,89 @@
+//! Back-references from bibliography entries to their citations.
+
+mod common;
+
+use common::{process, process_with, NOTE_STYLE, SECTIONS_BIB};
+use markdown_bib_processor::ProcessingOptions;
+
+fn backlinks() -> ProcessingOptions {
+    ProcessingOptions { backlinks: true, ..ProcessingOptions::default() }
+}
+
+/// The `cite-N` ids given out in `text`, in order.
+fn citation_ids(text: &str) -> Vec<&str> {
+    text.split("<a id=\"").skip(1).filter_map(|rest| rest.split('"').next()).collect()
+}
+
+/// The back-references following the entry anchored `anchor`.
+fn backrefs<'a>(bibliography: &'a str, anchor: &str) -> &'a str {
+    let target = format!(" id=\"{}\"></a>", anchor);
+    let line = bibliography.lines().find(|line| line.contains(&target)).unwrap();
+    line.split_once(&target).unwrap().1
+}
+
+#[test]
+fn citations_get_unique_ids_in_document_order() {
+    let markdown = "[@Cohen63] and [@Godel40], again [@Cohen63]; [@Nobody99] is unknown.\n";
+    let (markdown, bibliography) = process(markdown, SECTIONS_BIB, &backlinks());
+
+    assert_eq!(citation_ids(&markdown), ["cite-1", "cite-2", "cite-3"]);
+    assert!(markdown.starts_with("[<a id=\"cite-1\"></a>[[Cohen63]](#Cohen63)]"));
+    assert!(markdown.contains("[@Nobody99]"));
+    assert_eq!(
+        backrefs(&bibliography, "Cohen63"),
+        " \u{21a9} <a href=\"#cite-1\">1</a>, <a href=\"#cite-3\">3</a>"
+    );
+    assert_eq!(backrefs(&bibliography, "Godel40"), " \u{21a9} <a href=\"#cite-2\">2</a>");
+}
+
+#[test]
+fn uncited_entries_have_no_backrefs() {
+    let options = ProcessingOptions { nocite: vec!["Kanamori08".to_string()], ..backlinks() };
+    let (_, bibliography) = process("[@Cohen63]\n", SECTIONS_BIB, &options);
+
+    assert_eq!(backrefs(&bibliography, "Kanamori08"), "");
+    assert!(!backrefs(&bibliography, "Cohen63").is_empty());
+}
+
+#[test]
+fn numbering_runs_across_sections() {
+    let markdown = "# One\n\n[@Cohen63]\n\n# Two\n\n[@Cohen63] and [@Godel40]\n";
+    let options = ProcessingOptions { bibliography_scope_level: Some(1), ..backlinks() };
+    let (markdown, bibliography) = process(markdown, SECTIONS_BIB, &options);
+
+    assert_eq!(citation_ids(&markdown), ["cite-1", "cite-2", "cite-3"]);
+    // Each section's entry only links back to the citations in that section.
+    assert_eq!(backrefs(&markdown, "Cohen63-1"), " \u{21a9} <a href=\"#cite-1\">1</a>");
+    assert_eq!(backrefs(&bibliography, "Cohen63-2"), " \u{21a9} <a href=\"#cite-2\">2</a>");
+    assert_eq!(backrefs(&bibliography, "Godel40-2"), " \u{21a9} <a href=\"#cite-3\">3</a>");
+}
+
+#[test]
+fn entry_in_several_lists_has_one_backref_list() {
+    let markdown = "[@Cohen66] and [@Cohen66].\n\n\
+        <!-- bibliography type=book title=\"Books\" -->\n\
+        <!-- bibliography keyword=forcing title=\"Forcing\" -->\n";
+    let (markdown, _) = process(markdown, SECTIONS_BIB, &backlinks());
+
+    assert_eq!(markdown.matches('\u{21a9}').count(), 1);
+    assert_eq!(
+        backrefs(&markdown, "Cohen66"),
+        " \u{21a9} <a href=\"#cite-1\">1</a>, <a href=\"#cite-2\">2</a>"
+    );
+}
+
+#[test]
+fn every_key_of_a_note_gets_an_id() {
+    let markdown = "[@Godel40; @Cohen66] and [@Godel40].\n";
+    let output = process_with(markdown, &[SECTIONS_BIB], NOTE_STYLE, "", &backlinks()).unwrap();
+    let (markdown, bibliography) = (output.modified_markdown, output.bibliography_markdown);
+
+    assert!(markdown.starts_with(
+        "<a id=\"cite-1\"></a><a id=\"cite-2\"></a>[^1] and <a id=\"cite-3\"></a>[^2]."
+    ));
+    assert_eq!(
+        backrefs(&bibliography, "Godel40"),
+        " \u{21a9} <a href=\"#cite-1\">1</a>, <a href=\"#cite-3\">3</a>"
+    );
+    assert_eq!(backrefs(&bibliography, "Cohen66"), " \u{21a9} <a href=\"#cite-2\">2</a>");
+}
@@ -0,0 +1,176 @@
+//! Detecting bibliography formats and reading CSL-JSON and Hayagriva YAML.
+
+use markdown_bib_processor::{import_bibliography, process_markdown_and_bibtex, BibliographyFormat};
+use std::path::Path;
+
+const CSL_JSON: &str = r#"[
+  {
+    "id": "Cohen63",
+    "type": "article-journal",
+    "title": "The Independence of the Continuum Hypothesis",
+    "container-title": "Proceedings of the National Academy of Sciences",
+    "container-title-short": "PNAS",
+    "volume": 50,
+    "issue": "6",
+    "page": "1143-1148",
+    "DOI": "10.1073/pnas.50.6.1143",
+    "author": [
+      { "family": "Cohen", "given": "Paul J." },
+      { "literal": "The Stanford Logic Group" }
+    ],
+    "issued": { "date-parts": [[1963, 12, 15]] }
+  },
+  {
+    "id": "Tolkien54",
+    "type": "book",
+    "title": "The Fellowship of the Ring",
+    "publisher": "Allen & Unwin",
+    "publisher-place": "London",
+    "author": [{ "family": "Tolkien", "given": "J. R. R.", "dropping-particle": "von" }],
+    "issued": { "raw": "1954-07-29" }
+  }
+]"#;
+
+#[test]
+fn formats_are_detected_from_the_extension() {
+    let format = |path: &str| BibliographyFormat::from_path(Path::new(path));
+    assert_eq!(format("refs.bib"), Some(BibliographyFormat::BibTeX));
+    assert_eq!(format("refs.BibLaTeX"), Some(BibliographyFormat::BibTeX));
+    assert_eq!(format("zotero/export.json"), Some(BibliographyFormat::CslJson));
+    assert_eq!(format("refs.yml"), Some(BibliographyFormat::Yaml));
+    assert_eq!(format("refs.yaml"), Some(BibliographyFormat::Yaml));
+    assert_eq!(format("refs.ris"), Some(BibliographyFormat::Ris));
+    assert_eq!(format("endnote.xml"), Some(BibliographyFormat::EndNoteXml));
+    assert_eq!(format("notes.txt"), None);
+    assert_eq!(format("refs"), None);
+}
+
+#[test]
+fn formats_are_detected_from_the_content() {
+    let detect = BibliographyFormat::detect;
+    assert_eq!(detect(CSL_JSON), BibliographyFormat::CslJson);
+    assert_eq!(detect("\u{feff}  {\"id\": \"a\"}"), BibliographyFormat::CslJson);
+    assert_eq!(detect("% comment\n@article{a,\n title = {T}\n}"), BibliographyFormat::BibTeX);
+    assert_eq!(detect("- id: a\n  type: book\n"), BibliographyFormat::CslYaml);
+    assert_eq!(detect("references:\n- id: a\n"), BibliographyFormat::CslYaml);
+    assert_eq!(detect("harry:\n  type: book\n  title: Harry Potter\n"), BibliographyFormat::Yaml);
+    // Anything else is left to the BibTeX parser.
+    assert_eq!(detect("no entries here"), BibliographyFormat::BibTeX);
+}
+
+#[test]
+fn csl_json_items_are_mapped() {
+    let (library, unmapped) = import_bibliography(CSL_JSON, None).unwrap();
+    assert!(unmapped.is_empty());
+    assert_eq!(library.len(), 2);
+
+    let cohen = library.get("Cohen63").unwrap();
+    assert_eq!(cohen.title().unwrap().to_string(), "The Independence of the Continuum Hypothesis");
+    let authors = cohen.authors().unwrap();
+    let name = |i: usize| (authors[i].name.as_str(), authors[i].given_name.as_deref());
+    assert_eq!(name(0), ("Cohen", Some("Paul J.")));
+    assert_eq!(name(1), ("The Stanford Logic Group", None));
+    let date = cohen.date().unwrap();
+    // hayagriva counts months and days from zero.
+    assert_eq!((date.year, date.month, date.day), (1963, Some(11), Some(14)));
+    assert_eq!(cohen.doi(), Some("10.1073/pnas.50.6.1143"));
+    assert_eq!(cohen.page_range().map(ToString::to_string).as_deref(), Some("1143-1148"));
+
+    // The container and its volume and issue become the periodical parent.
+    let journal = &cohen.parents()[0];
+    let title = journal.title().unwrap();
+    assert_eq!(title.to_string(), "Proceedings of the National Academy of Sciences");
+    assert_eq!(title.short.as_ref().map(ToString::to_string).as_deref(), Some("PNAS"));
+    assert_eq!(journal.volume().map(ToString::to_string).as_deref(), Some("50"));
+    assert_eq!(journal.issue().map(ToString::to_string).as_deref(), Some("6"));
+
+    // Without a container, the publisher stays on the book.
+    let tolkien = library.get("Tolkien54").unwrap();
+    assert!(tolkien.parents().is_empty());
+    let publisher = tolkien.publisher().unwrap();
+    assert_eq!(publisher.name().map(ToString::to_string).as_deref(), Some("Allen & Unwin"));
+    assert_eq!(publisher.location().map(ToString::to_string).as_deref(), Some("London"));
+    assert_eq!(tolkien.authors().unwrap()[0].prefix.as_deref(), Some("von"));
+    assert_eq!(tolkien.date().unwrap().year, 1954);
+}
+
+#[test]
+fn csl_json_errors_are_reported() {
+    assert!(import_bibliography("[{\"id\": ", Some(BibliographyFormat::CslJson)).is_err());
+    assert!(import_bibliography("42", Some(BibliographyFormat::CslJson)).is_err());
+}
+
+const HAYAGRIVA_YAML: &str = r#"
+turing50:
+  type: article
+  title: Computing Machinery and Intelligence
+  author: Turing, Alan M.
+  date: 1950-10
+  page-range: 433-460
+  serial-number:
+    doi: 10.1093/mind/LIX.236.433
+  parent:
+    type: periodical
+    title: Mind
+    volume: 59
+    issue: 236
+
+knuth69:
+  type: chapter
+  title: Random Numbers
+  author: Knuth, Donald E.
+  parent:
+    type: book
+    title: Seminumerical Algorithms
+    date: 1969
+    publisher:
+      name: Addison-Wesley
+      location: Reading, MA
+    parent:
+      type: book
+      title: The Art of Computer Programming
+      volume: 2
+"#;
+
+#[test]
+fn hayagriva_yaml_entries_keep_their_parents() {
+    assert_eq!(BibliographyFormat::detect(HAYAGRIVA_YAML), BibliographyFormat::Yaml);
+    let (library, unmapped) = import_bibliography(HAYAGRIVA_YAML, None).unwrap();
+    assert!(unmapped.is_empty());
+    assert_eq!(library.len(), 2);
+
+    let turing = library.get("turing50").unwrap();
+    assert_eq!(turing.authors().unwrap()[0].name, "Turing");
+    assert_eq!(turing.date().unwrap().year, 1950);
+    assert_eq!(turing.doi(), Some("10.1093/mind/LIX.236.433"));
+    let mind = &turing.parents()[0];
+    assert_eq!(mind.title().unwrap().to_string(), "Mind");
+    assert_eq!(mind.volume().map(ToString::to_string).as_deref(), Some("59"));
+
+    // A parent of a parent, and a date only given on the parent.
+    let knuth = library.get("knuth69").unwrap();
+    assert!(knuth.date().is_none());
+    let book = &knuth.parents()[0];
+    assert_eq!(book.title().unwrap().to_string(), "Seminumerical Algorithms");
+    assert_eq!(book.date().unwrap().year, 1969);
+    let series = &book.parents()[0];
+    assert_eq!(series.title().unwrap().to_string(), "The Art of Computer Programming");
+}
+
+#[test]
+fn hayagriva_yaml_entries_are_cited_by_short_keys() {
+    let csl_style = include_str!("../chicago.csl");
+    let markdown = "See [@Turing50] and [@Knuth69].";
+    let output = process_markdown_and_bibtex(markdown, HAYAGRIVA_YAML, "", csl_style, "").unwrap();
+
+    assert!(output.modified_markdown.contains("(#Turing50)"));
+    // Dated by its book.
+    assert!(output.modified_markdown.contains("(#Knuth69)"));
+    assert!(output.bibliography_markdown.contains("Seminumerical Algorithms"));
+}
+
+#[test]
+fn hayagriva_yaml_errors_are_reported() {
+    let error = import_bibliography("harry:\n  type: [book\n", Some(BibliographyFormat::Yaml));
+    assert!(error.unwrap_err().starts_with("Hayagriva YAML parsing error"));
+}
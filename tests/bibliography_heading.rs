@@ -0,0 +1,50 @@
+//! The bibliography heading, from the options, the CSL style or the locale.
+
+mod common;
+
+use common::{process_with, COHEN63, CSL_STYLE};
+use markdown_bib_processor::{process_markdown_and_bibtex, ProcessingOptions};
+
+const EN_US: &str = include_str!("../locales-en-US.xml");
+
+const MARKDOWN: &str = "As shown in [@Cohen63].";
+
+fn heading(options: &ProcessingOptions, locale: &str) -> String {
+    let output = process_with(MARKDOWN, &[COHEN63], CSL_STYLE, locale, options).unwrap();
+    output.bibliography_markdown.lines().next().unwrap_or_default().to_string()
+}
+
+#[test]
+fn english_heading_is_the_references_term() {
+    let output = process_markdown_and_bibtex(MARKDOWN, COHEN63, "", CSL_STYLE, EN_US).unwrap();
+    assert!(output.bibliography_markdown.starts_with("### References\n"));
+    // Without a locale file, the built-in en-US locale gives the same.
+    assert_eq!(heading(&ProcessingOptions::default(), ""), "### References");
+}
+
+#[test]
+fn german_heading_comes_from_the_locale() {
+    let options = ProcessingOptions { lang: Some("de-DE".to_string()), ..ProcessingOptions::default() };
+    // The built-in de-DE locale only has the plural of `reference`.
+    assert_eq!(heading(&options, ""), "### Referenzen");
+    // A locale file's `bibliography` term wins, and without `lang` its
+    // language is the document's.
+    let locale = EN_US
+        .replace("xml:lang=\"en-US\"", "xml:lang=\"de-DE\"")
+        .replace("<terms>", "<terms>\n    <term name=\"bibliography\">Literaturverzeichnis</term>");
+    assert_eq!(heading(&options, &locale), "### Literaturverzeichnis");
+    assert_eq!(heading(&ProcessingOptions::default(), &locale), "### Literaturverzeichnis");
+    // But only in its own language.
+    let english = ProcessingOptions { lang: Some("en-US".to_string()), ..ProcessingOptions::default() };
+    assert_eq!(heading(&english, &locale), "### References");
+}
+
+#[test]
+fn title_option_overrides_the_locale() {
+    let options = ProcessingOptions {
+        bibliography_title: Some("Works Cited".to_string()),
+        lang: Some("de-DE".to_string()),
+        ..ProcessingOptions::default()
+    };
+    assert_eq!(heading(&options, EN_US), "### Works Cited");
+}
@@ -0,0 +1,119 @@
+//! `@string` abbreviations, `crossref` and `xdata` in BibTeX bibliographies.
+
+use markdown_bib_processor::{
+    extract_cited_bibliography, import_bibliography, process_markdown_with_bibliographies,
+    BibliographyFormat, BibliographySource, DuplicatePrecedence, ProcessingOptions,
+};
+
+const PROCEEDINGS: &str = include_str!("fixtures/proceedings.bib");
+const XDATA: &str = include_str!("fixtures/xdata.bib");
+const MULTIVOLUME: &str = include_str!("fixtures/multivolume.bib");
+const CSL_STYLE: &str = include_str!("../chicago.csl");
+
+fn sources() -> Vec<BibliographySource> {
+    [("proceedings.bib", PROCEEDINGS), ("xdata.bib", XDATA), ("multivolume.bib", MULTIVOLUME)]
+        .into_iter()
+        .map(|(name, content)| BibliographySource {
+            name: name.to_string(),
+            content: content.to_string(),
+            format: Some(BibliographyFormat::BibTeX),
+        })
+        .collect()
+}
+
+#[test]
+fn string_abbreviations_are_expanded() {
+    let (library, _) = import_bibliography(PROCEEDINGS, Some(BibliographyFormat::BibTeX)).unwrap();
+    let cohen = library.get("Cohen63").unwrap();
+    let journal = cohen.parents()[0].title().unwrap().to_string();
+    assert_eq!(journal, "Proceedings of the National Academy of Sciences");
+    assert_eq!(cohen.date().unwrap().month, Some(11));
+
+    let stoc = library.get("STOC71").unwrap().title().unwrap().to_string();
+    assert!(stoc.starts_with("Proceedings of the Annual ACM Symposium"));
+    assert!(stoc.ends_with(", Shaker Heights, Ohio"));
+}
+
+#[test]
+fn crossref_fields_are_inherited() {
+    let (library, _) = import_bibliography(PROCEEDINGS, Some(BibliographyFormat::BibTeX)).unwrap();
+    let cook = library.get("Cook71").unwrap();
+    assert_eq!(cook.date().unwrap().year, 1971);
+    let booktitle = cook.parents()[0].title().unwrap().to_string();
+    assert!(booktitle.contains("Symposium on Theory of Computing"));
+    let editors = cook.editors().or_else(|| cook.parents()[0].editors());
+    assert_eq!(editors.map(<[_]>::len), Some(3));
+}
+
+#[test]
+fn xdata_fields_are_inherited_and_containers_are_not_entries() {
+    let (library, _) = import_bibliography(XDATA, Some(BibliographyFormat::BibTeX)).unwrap();
+    assert!(library.get("knuth:taocp").is_none());
+    assert_eq!(library.len(), 3);
+
+    for key in ["Knuth68", "Knuth69", "Knuth69b"] {
+        let entry = library.get(key).unwrap();
+        assert_eq!(entry.authors().unwrap()[0].name, "Knuth", "{}", key);
+    }
+    // Through `crossref` to an entry that itself uses `xdata`.
+    assert_eq!(library.get("Knuth69b").unwrap().date().unwrap().year, 1969);
+}
+
+#[test]
+fn short_keys_find_inherited_authors_and_years() {
+    let markdown = "Cook [@cook71] built on Knuth [@knuth69b].";
+    let output = process_markdown_with_bibliographies(
+        markdown,
+        &sources(),
+        "",
+        CSL_STYLE,
+        "",
+        &ProcessingOptions::default(),
+    )
+    .unwrap();
+
+    assert!(output.modified_markdown.contains("(#cook71)"));
+    assert!(output.modified_markdown.contains("(#knuth69b)"));
+    assert!(output.bibliography_markdown.contains("The Complexity of Theorem-Proving Procedures"));
+    assert!(output.bibliography_markdown.contains("Seminumerical Algorithms"));
+    // Only the cited entries are listed.
+    assert_eq!(output.bibliography_markdown.matches("<a href=").count(), 2);
+}
+
+#[test]
+fn extraction_keeps_definitions_and_parents() {
+    let extracted = extract_cited_bibliography(
+        "See [@cook71].",
+        &sources(),
+        BibliographyFormat::BibTeX,
+        DuplicatePrecedence::First,
+    )
+    .unwrap();
+
+    assert!(extracted.contains("@string{stoc ="));
+    assert!(extracted.contains("@xdata{knuth:taocp,"));
+    assert!(extracted.contains("@inproceedings{Cook71,"));
+    assert!(extracted.contains("@proceedings{STOC71,"));
+    assert!(!extracted.contains("@article{Cohen63,"));
+
+    // The extracted file reads back with the same resolution.
+    let (library, _) = import_bibliography(&extracted, Some(BibliographyFormat::BibTeX)).unwrap();
+    assert_eq!(library.get("Cook71").unwrap().date().unwrap().year, 1971);
+}
+
+#[test]
+fn extraction_follows_parents_of_parents() {
+    // The chapter inherits from its volume, which inherits from the multi-volume work.
+    let extracted = extract_cited_bibliography(
+        "See [@knuth73].",
+        &sources(),
+        BibliographyFormat::BibTeX,
+        DuplicatePrecedence::First,
+    )
+    .unwrap();
+
+    assert!(extracted.contains("@inbook{Knuth73a,"));
+    assert!(extracted.contains("@book{Knuth73,"));
+    assert!(extracted.contains("@mvbook{TAOCP,"));
+    assert!(!extracted.contains("@book{Knuth68,"));
+}
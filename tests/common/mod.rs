@@ -0,0 +1,52 @@
+//! Styles, bibliographies and processing helpers shared by the integration
+//! tests. Each test crate uses only some of them.
+#![allow(dead_code)]
+
+use markdown_bib_processor::{
+    process_markdown_with_bibliographies, BibliographyFormat, BibliographySource,
+    ProcessingOptions, ProcessingOutput,
+};
+
+pub const CSL_STYLE: &str = include_str!("../../chicago.csl");
+pub const NOTE_STYLE: &str = include_str!("../fixtures/note.csl");
+pub const SECTIONS_BIB: &str = include_str!("../fixtures/sections.bib");
+
+pub const COHEN63: &str = r#"
+@article{Cohen63,
+  author  = {Paul J. Cohen},
+  title   = {The Independence of the Continuum Hypothesis},
+  journal = {Proceedings of the National Academy of Sciences},
+  year    = {1963}
+}
+"#;
+
+/// BibTeX files all named `refs.bib`, as from `*/refs.bib`.
+pub fn bibtex_sources(contents: &[&str]) -> Vec<BibliographySource> {
+    contents
+        .iter()
+        .map(|content| BibliographySource {
+            name: "refs.bib".to_string(),
+            content: content.to_string(),
+            format: Some(BibliographyFormat::BibTeX),
+        })
+        .collect()
+}
+
+/// Processes `markdown` with the BibTeX files `bibtex`, a CSL style and a
+/// locale file (or `""`).
+pub fn process_with(
+    markdown: &str,
+    bibtex: &[&str],
+    style: &str,
+    locale: &str,
+    options: &ProcessingOptions,
+) -> Result<ProcessingOutput, String> {
+    let sources = bibtex_sources(bibtex);
+    process_markdown_with_bibliographies(markdown, &sources, "", style, locale, options)
+}
+
+/// The processed Markdown and the bibliography after it, in the Chicago style.
+pub fn process(markdown: &str, bibtex: &str, options: &ProcessingOptions) -> (String, String) {
+    let output = process_with(markdown, &[bibtex], CSL_STYLE, "", options).unwrap();
+    (output.modified_markdown, output.bibliography_markdown)
+}
@@ -0,0 +1,30 @@
+//! The repair step of document processing, which leaves finished text alone.
+
+mod common;
+
+use common::{process, COHEN63};
+use markdown_bib_processor::{ProcessingOptions, RepairOptions};
+
+#[test]
+fn complete_documents_keep_their_last_line() {
+    let options = ProcessingOptions::default();
+    for ending in ["Maintained by @leonardopedro", "Let $x", "| a | b |"] {
+        let markdown = format!("As shown in [@Cohen63].\n\n{}", ending);
+        assert!(process(&markdown, COHEN63, &options).0.ends_with(ending), "{:?}", ending);
+    }
+}
+
+#[test]
+fn streamed_tails_hold_back_partial_tokens() {
+    let options = ProcessingOptions {
+        repair: RepairOptions { trailing_block_only: true, ..RepairOptions::default() },
+        ..ProcessingOptions::default()
+    };
+    let process_tail = |ending: &str| {
+        process(&format!("As shown in [@Cohen63].\n\n{}", ending), COHEN63, &options).0
+    };
+
+    assert!(process_tail("As shown by @Coh").ends_with("As shown by "));
+    assert!(process_tail("Let $x").ends_with("Let $x$"));
+    assert!(process_tail("| a | b |").ends_with("| a | b |\n| --- | --- |"));
+}
@@ -0,0 +1,110 @@
+//! Bibliographies embedded in the document: a front-matter `references` list
+//! and ```` ```bibtex ```` blocks.
+
+mod common;
+
+use common::{process_with, CSL_STYLE};
+use markdown_bib_processor::{DuplicatePrecedence, MergeConflict, ProcessingOptions, ProcessingOutput};
+
+const FRONT_MATTER: &str = "---
+title: Computability
+references:
+- id: Turing36
+  type: article-journal
+  author: [{family: Turing, given: Alan M.}]
+  title: On computable numbers
+  container-title: Proceedings of the London Mathematical Society
+  issued: {date-parts: [[1936]]}
+---
+";
+
+const EXTERNAL: &str = r#"
+@article{Turing36,
+  author  = {Alan M. Turing},
+  title   = {Computing Machinery and Intelligence},
+  journal = {Mind},
+  year    = {1936}
+}
+
+@article{Church36,
+  author  = {Alonzo Church},
+  title   = {An Unsolvable Problem of Elementary Number Theory},
+  journal = {American Journal of Mathematics},
+  year    = {1936}
+}
+"#;
+
+fn process(
+    markdown: &str,
+    external: &[&str],
+    precedence: DuplicatePrecedence,
+) -> Result<ProcessingOutput, String> {
+    let options = ProcessingOptions { duplicate_precedence: precedence, ..ProcessingOptions::default() };
+    process_with(markdown, external, CSL_STYLE, "", &options)
+}
+
+#[test]
+fn front_matter_references_are_cited() {
+    let markdown = format!("{}\nSee [@Turing36].\n", FRONT_MATTER);
+    let output = process(&markdown, &[], DuplicatePrecedence::First).unwrap();
+
+    assert!(output.modified_markdown.starts_with(FRONT_MATTER));
+    assert!(output.modified_markdown.contains("[[Turing36]](#Turing36)"));
+    assert!(output.bibliography_markdown.contains("On Computable Numbers"));
+}
+
+#[test]
+fn bibtex_blocks_are_read_and_removed() {
+    let markdown = "See [@Church36] and [@Kleene52].\n\n\
+        ```bibtex\n\
+        @article{Church36, author = {Church, Alonzo}, title = {An Unsolvable Problem}, year = {1936}}\n\
+        ```\n\n\
+        ~~~biblatex\n\
+        @book{Kleene52, author = {Kleene, Stephen C.}, title = {Introduction to Metamathematics}, year = {1952}}\n\
+        ~~~\n\n\
+        ```rust\n\
+        let bib = \"@book{x}\";\n\
+        ```\n";
+    let output = process(markdown, &[], DuplicatePrecedence::First).unwrap();
+
+    assert!(!output.modified_markdown.contains("@article"));
+    assert!(!output.modified_markdown.contains("@book{Kleene52"));
+    // Other code blocks stay.
+    assert!(output.modified_markdown.contains("```rust\nlet bib = \"@book{x}\";\n```"));
+    assert!(output.bibliography_markdown.contains("An Unsolvable Problem"));
+    assert!(output.bibliography_markdown.contains("Introduction to Metamathematics"));
+}
+
+#[test]
+fn embedded_and_external_entries_are_merged() {
+    let markdown = format!("{}\nSee [@Turing36] and [@Church36].\n", FRONT_MATTER);
+    for precedence in [DuplicatePrecedence::First, DuplicatePrecedence::Last, DuplicatePrecedence::Error] {
+        let output = process(&markdown, &[EXTERNAL], precedence).unwrap();
+
+        // The document's own entry wins whatever the precedence.
+        assert!(output.bibliography_markdown.contains("On Computable Numbers"), "{:?}", precedence);
+        assert!(!output.bibliography_markdown.contains("Computing Machinery"), "{:?}", precedence);
+        assert!(output.bibliography_markdown.contains("An Unsolvable Problem"), "{:?}", precedence);
+        assert_eq!(
+            output.merge_conflicts,
+            [MergeConflict::DuplicateKey {
+                key: "Turing36".to_string(),
+                kept: "front matter".to_string(),
+                dropped: "refs.bib".to_string(),
+            }],
+            "{:?}",
+            precedence
+        );
+    }
+}
+
+#[test]
+fn precedence_still_applies_between_external_files() {
+    let markdown = format!("{}\nSee [@Church36].\n", FRONT_MATTER);
+    let other = EXTERNAL.replace("An Unsolvable Problem", "A Note on the Entscheidungsproblem");
+
+    let output = process(&markdown, &[EXTERNAL, &other], DuplicatePrecedence::Last).unwrap();
+    assert!(output.bibliography_markdown.contains("A Note on the Entscheidungsproblem"));
+    let error = process(&markdown, &[EXTERNAL, &other], DuplicatePrecedence::Error).err().unwrap();
+    assert!(error.starts_with("duplicate key `"), "{}", error);
+}
@@ -0,0 +1,154 @@
+//! Exported bibliographies read back as the entries they were written from.
+
+use markdown_bib_processor::{
+    import_bibliography, process_markdown_and_bibtex_with_options, BibliographyFormat,
+    ProcessingOptions,
+};
+
+const CSL_STYLE: &str = include_str!("../chicago.csl");
+
+const BIBTEX: &str = r#"
+@article{Cohen63,
+  author   = {Paul J. Cohen},
+  title    = {The Independence of the Continuum Hypothesis},
+  journal  = {Proceedings of the National Academy of Sciences},
+  volume   = {50},
+  number   = {6},
+  pages    = {1143--1148},
+  month    = dec,
+  year     = {1963},
+  doi      = {10.1073/pnas.50.6.1143}
+}
+
+@incollection{Gogh88,
+  author    = {van {Gogh}},
+  editor    = {Ludwig van Beethoven},
+  title     = {Letters \& Sketches},
+  booktitle = {Collected Writings},
+  publisher = {Thames},
+  address   = {London},
+  pages     = {10--20},
+  year      = {1888}
+}
+"#;
+
+fn export(format: BibliographyFormat) -> String {
+    let options = ProcessingOptions { export_format: Some(format), ..ProcessingOptions::default() };
+    let output = process_markdown_and_bibtex_with_options(
+        "[@Cohen63] and [@Gogh88]",
+        BIBTEX,
+        "",
+        CSL_STYLE,
+        "",
+        &options,
+    )
+    .unwrap();
+    output.exported_bibliography.unwrap()
+}
+
+fn assert_round_trip(format: BibliographyFormat) {
+    let exported = export(format);
+    let (library, unmapped) = import_bibliography(&exported, Some(format)).unwrap();
+    assert!(unmapped.is_empty(), "{:?}: {:?}", format, unmapped);
+    assert_eq!(library.len(), 2, "{:?}", format);
+
+    let cohen = library.get("Cohen63").unwrap();
+    assert_eq!(cohen.title().unwrap().to_string(), "The Independence of the Continuum Hypothesis");
+    let author = &cohen.authors().unwrap()[0];
+    assert_eq!((author.name.as_str(), author.given_name.as_deref()), ("Cohen", Some("Paul J.")));
+    assert_eq!(cohen.date().unwrap().year, 1963);
+    assert_eq!(cohen.doi(), Some("10.1073/pnas.50.6.1143"));
+    let journal = &cohen.parents()[0];
+    assert_eq!(journal.title().unwrap().to_string(), "Proceedings of the National Academy of Sciences");
+    assert_eq!(cohen.volume().or_else(|| journal.volume()).map(ToString::to_string), Some("50".into()));
+
+    let gogh = library.get("Gogh88").unwrap();
+    assert_eq!(gogh.title().unwrap().to_string(), "Letters & Sketches");
+    // RIS has no name particles, so the prefix stays in the family name.
+    let author = &gogh.authors().unwrap()[0];
+    let name = [author.prefix.as_deref(), Some(author.name.as_str())];
+    assert_eq!(name.into_iter().flatten().collect::<Vec<_>>().join(" "), "van Gogh", "{:?}", format);
+    if format != BibliographyFormat::Ris {
+        assert_eq!(author.prefix.as_deref(), Some("van"), "{:?}", format);
+    }
+    let book = &gogh.parents()[0];
+    assert_eq!(book.title().unwrap().to_string(), "Collected Writings");
+    let editor = &gogh.editors().or_else(|| book.editors()).unwrap()[0];
+    assert_eq!(editor.given_name.as_deref(), Some("Ludwig"), "{:?}", format);
+    assert!(editor.name.ends_with("Beethoven"), "{:?}", format);
+}
+
+#[test]
+fn csl_json_round_trip() {
+    assert_round_trip(BibliographyFormat::CslJson);
+}
+
+#[test]
+fn csl_yaml_round_trip() {
+    assert!(export(BibliographyFormat::CslYaml).starts_with("references:\n"));
+    assert_round_trip(BibliographyFormat::CslYaml);
+}
+
+#[test]
+fn bibtex_round_trip() {
+    let exported = export(BibliographyFormat::BibTeX);
+    assert!(exported.contains("  author = {van {Gogh}},"), "{}", exported);
+    assert!(exported.contains("  month = dec,"));
+    assert_round_trip(BibliographyFormat::BibTeX);
+}
+
+#[test]
+fn ris_round_trip() {
+    assert_round_trip(BibliographyFormat::Ris);
+}
+
+#[test]
+fn ris_names_keep_their_suffix() {
+    let bibtex = r#"
+@book{King63,
+  author    = {King, Jr., Martin Luther},
+  title     = {Strength to Love},
+  publisher = {Harper \& Row},
+  year      = {1963}
+}
+"#;
+    let options =
+        ProcessingOptions { export_format: Some(BibliographyFormat::Ris), ..ProcessingOptions::default() };
+    let output =
+        process_markdown_and_bibtex_with_options("[@King63]", bibtex, "", CSL_STYLE, "", &options).unwrap();
+    let exported = output.exported_bibliography.unwrap();
+    assert!(exported.contains("AU  - King, Martin Luther, Jr.\n"), "{}", exported);
+
+    let (library, _) = import_bibliography(&exported, Some(BibliographyFormat::Ris)).unwrap();
+    let author = &library.get("King63").unwrap().authors().unwrap()[0];
+    assert_eq!(author.name, "King");
+    assert_eq!(author.given_name.as_deref(), Some("Martin Luther"));
+    assert_eq!(author.suffix.as_deref(), Some("Jr."));
+}
+
+#[test]
+fn ris_values_stay_on_one_line() {
+    let csl_json = r#"[{
+        "id": "Cohen63",
+        "type": "article-journal",
+        "title": "The Independence of the Continuum Hypothesis",
+        "author": [{ "family": "Cohen", "given": "Paul J." }],
+        "issued": { "date-parts": [[1963]] },
+        "abstract": "A proof that the continuum hypothesis\nis independent of ZFC.\n\nPart I.",
+        "note": "First line\r\nsecond line"
+    }]"#;
+    let options =
+        ProcessingOptions { export_format: Some(BibliographyFormat::Ris), ..ProcessingOptions::default() };
+    let output =
+        process_markdown_and_bibtex_with_options("[@Cohen63]", csl_json, "", CSL_STYLE, "", &options)
+            .unwrap();
+    let exported = output.exported_bibliography.unwrap();
+
+    assert!(exported.lines().all(|line| line.is_empty() || line.get(2..6) == Some("  - ")), "{}", exported);
+    assert!(exported.contains("AB  - A proof that the continuum hypothesis is independent of ZFC. Part I.\n"));
+    assert!(exported.contains("N1  - First line second line\n"));
+
+    let (library, _) = import_bibliography(&exported, Some(BibliographyFormat::Ris)).unwrap();
+    let abstract_ = library.get("Cohen63").unwrap().abstract_().unwrap().to_string();
+    assert_eq!(abstract_, "A proof that the continuum hypothesis is independent of ZFC. Part I.");
+}
@@ -0,0 +1,121 @@
+//! Settings read from the YAML front matter, and their precedence.
+
+mod common;
+
+use common::{process, process_with, CSL_STYLE};
+use markdown_bib_processor::{FrontMatter, ProcessingOptions};
+use std::path::{Path, PathBuf};
+
+const BIBTEX: &str = r#"
+@article{Cohen63,
+  author  = {Paul J. Cohen},
+  title   = {The Independence of the Continuum Hypothesis},
+  journal = {Proceedings of the National Academy of Sciences},
+  year    = {1963}
+}
+
+@book{Godel40,
+  author    = {Kurt G{\"o}del},
+  title     = {The Consistency of the Continuum Hypothesis},
+  publisher = {Princeton University Press},
+  year      = {1940}
+}
+"#;
+
+const MARKDOWN: &str = "---
+bibliography: [refs/lab.bib, /srv/shared.bib]
+csl: ../styles/chicago.csl
+lang: de-DE
+link-citations: false
+reference-section-title: Quellen
+nocite: \"@Godel40\"
+---
+As shown in [@Cohen63].
+";
+
+#[test]
+fn paths_are_resolved_against_the_markdown_file() {
+    let front_matter = FrontMatter::parse(MARKDOWN).unwrap();
+    let markdown_path = Path::new("papers/draft/paper.md");
+    assert_eq!(
+        front_matter.bibliography_paths(markdown_path),
+        [PathBuf::from("papers/draft/refs/lab.bib"), PathBuf::from("/srv/shared.bib")]
+    );
+    assert_eq!(
+        front_matter.csl_path(markdown_path),
+        Some(PathBuf::from("papers/draft/../styles/chicago.csl"))
+    );
+    // A file in the current directory.
+    assert_eq!(
+        front_matter.csl_path(Path::new("paper.md")),
+        Some(PathBuf::from("../styles/chicago.csl"))
+    );
+    assert_eq!(FrontMatter::parse("No front matter.").unwrap().csl_path(markdown_path), None);
+}
+
+#[test]
+fn front_matter_settings_apply_to_library_calls() {
+    let (markdown, bibliography) = process(MARKDOWN, BIBTEX, &ProcessingOptions::default());
+    // `link-citations: false`
+    assert!(markdown.contains("As shown in [[Cohen63]]."), "{}", markdown);
+    assert!(markdown.starts_with("---\nbibliography:"));
+    // `reference-section-title` and `nocite`
+    assert!(bibliography.starts_with("### Quellen\n"));
+    assert!(bibliography.contains("Consistency of the Continuum Hypothesis"));
+}
+
+#[test]
+fn options_override_the_front_matter() {
+    let options = ProcessingOptions {
+        bibliography_title: Some("Works Cited".to_string()),
+        nocite: vec!["Cohen63".to_string()],
+        lang: Some("en-US".to_string()),
+        link_citations: Some(true),
+        ..ProcessingOptions::default()
+    };
+    let (markdown, bibliography) = process(MARKDOWN, BIBTEX, &options);
+    assert!(markdown.contains("[[Cohen63]](#Cohen63)"), "{}", markdown);
+    assert!(bibliography.starts_with("### Works Cited\n"));
+    assert!(!bibliography.contains("Consistency of the Continuum Hypothesis"));
+
+    // The front matter's `lang` gives the heading when no title is set.
+    let without_title = MARKDOWN.replace("reference-section-title: Quellen\n", "");
+    let (_, bibliography) = process(&without_title, BIBTEX, &ProcessingOptions::default());
+    assert!(bibliography.starts_with("### Referenzen\n"), "{}", bibliography);
+    let (_, bibliography) = process(&without_title, BIBTEX, &options_with_lang("en-US"));
+    assert!(bibliography.starts_with("### References\n"), "{}", bibliography);
+}
+
+fn options_with_lang(lang: &str) -> ProcessingOptions {
+    ProcessingOptions { lang: Some(lang.to_string()), ..ProcessingOptions::default() }
+}
+
+#[test]
+fn front_matter_can_be_ignored() {
+    let options = ProcessingOptions { read_front_matter: false, ..ProcessingOptions::default() };
+    let (markdown, bibliography) = process(MARKDOWN, BIBTEX, &options);
+    assert!(markdown.contains("[[Cohen63]](#Cohen63)"));
+    assert!(bibliography.starts_with("### References\n"));
+    assert!(!bibliography.contains("Consistency"));
+}
+
+#[test]
+fn invalid_front_matter_is_an_error_unless_ignored() {
+    let markdown = "---\nlang: [de-DE\n---\nAs shown in [@Cohen63].\n";
+    let options = ProcessingOptions::default();
+    let error = process_with(markdown, &[BIBTEX], CSL_STYLE, "", &options).err().unwrap();
+    assert!(error.starts_with("Front matter parsing error"), "{}", error);
+
+    let options = ProcessingOptions { read_front_matter: false, ..ProcessingOptions::default() };
+    let (processed, _) = process(markdown, BIBTEX, &options);
+    assert!(processed.starts_with("---\nlang: [de-DE\n---\n"));
+    assert!(processed.contains("[[Cohen63]](#Cohen63)"));
+}
+
+#[test]
+fn thematic_break_and_setext_heading_are_not_front_matter() {
+    let markdown = "---\nIntro paragraph\n---\n\nAs shown in [@Cohen63].\n";
+    let options = ProcessingOptions { strip_front_matter: true, ..ProcessingOptions::default() };
+    let (processed, _) = process(markdown, BIBTEX, &options);
+    assert!(processed.starts_with("---\nIntro paragraph\n---\n\nAs shown in [[[Cohen63]](#Cohen63)].\n"));
+}
@@ -0,0 +1,52 @@
+//! LaTeX accents and special letters in BibTeX names and titles.
+
+use markdown_bib_processor::process_markdown_and_bibtex;
+
+const CSL_STYLE: &str = include_str!("../chicago.csl");
+
+const BIBTEX: &str = r#"
+@article{Gowers00,
+  author  = {W. T. Gowers},
+  title   = {The Two Cultures of Mathematics},
+  journal = {Mathematics: Frontiers and Perspectives},
+  year    = {2000}
+}
+
+@article{Godel31,
+  author  = {Kurt G{\"o}del},
+  title   = {{\"U}ber formal unentscheidbare S{\"a}tze},
+  journal = {Monatshefte f{\"u}r Mathematik und Physik},
+  year    = {1931}
+}
+
+@book{Lukasiewicz57,
+  author    = {Jan {\L}ukasiewicz},
+  title     = {Aristotle's Syllogistic},
+  publisher = {Clarendon Press},
+  year      = {1957}
+}
+"#;
+
+#[test]
+fn ascii_keys_find_accented_authors() {
+    let markdown = "Gödel [@Gode31], Łukasiewicz [@Lukasiewicz57] and Gowers [@Gowers00].";
+    let output = process_markdown_and_bibtex(markdown, BIBTEX, "", CSL_STYLE, "").unwrap();
+
+    assert!(output.modified_markdown.contains("(#Gode31)"));
+    assert!(output.modified_markdown.contains("(#Lukasiewicz57)"));
+    assert!(output.bibliography_markdown.contains("Gödel"));
+    assert!(output.bibliography_markdown.contains("Łukasiewicz"));
+    // Chicago title-cases the title.
+    assert!(output.bibliography_markdown.contains("Über Formal Unentscheidbare Sätze"));
+}
+
+#[test]
+fn accented_authors_sort_as_their_ascii_form() {
+    let markdown = "[@Gowers00] [@Gode31]";
+    let output = process_markdown_and_bibtex(markdown, BIBTEX, "", CSL_STYLE, "").unwrap();
+
+    // `Godel` sorts before `Gowers`, although `ö` comes after `w`.
+    let godel = output.bibliography_markdown.find("Gödel").unwrap();
+    let gowers = output.bibliography_markdown.find("Gowers").unwrap();
+    assert!(godel < gowers, "{}", output.bibliography_markdown);
+}
@@ -0,0 +1,117 @@
+//! Merging several bibliographies: duplicate keys and likely duplicates.
+
+mod common;
+
+use common::{bibtex_sources, process_with, CSL_STYLE};
+use markdown_bib_processor::{
+    extract_cited_bibliography, BibliographyFormat, BibliographySource, DuplicatePrecedence,
+    MergeConflict, ProcessingOptions, ProcessingOutput,
+};
+
+const SHARED: &str = r#"
+@article{Cohen63,
+  author  = {Paul J. Cohen},
+  title   = {The Independence of the Continuum Hypothesis},
+  journal = {Proceedings of the National Academy of Sciences},
+  year    = {1963},
+  doi     = {https://doi.org/10.1073/PNAS.50.6.1143}
+}
+"#;
+
+const PAPER: &str = r#"
+@book{Cohen63,
+  author    = {Paul J. Cohen},
+  title     = {Set Theory and the Continuum Hypothesis},
+  publisher = {Benjamin},
+  year      = {1963}
+}
+"#;
+
+/// Two files with the same name, as from `*/refs.bib`.
+fn sources() -> Vec<BibliographySource> {
+    bibtex_sources(&[SHARED, PAPER])
+}
+
+fn process(bibtex: &[&str], precedence: DuplicatePrecedence) -> Result<ProcessingOutput, String> {
+    let options = ProcessingOptions { duplicate_precedence: precedence, ..ProcessingOptions::default() };
+    process_with("See [@Cohen63].", bibtex, CSL_STYLE, "", &options)
+}
+
+fn extract(precedence: DuplicatePrecedence) -> String {
+    let format = BibliographyFormat::BibTeX;
+    extract_cited_bibliography("See [@Cohen63].", &sources(), format, precedence).unwrap()
+}
+
+#[test]
+fn first_bibliography_wins() {
+    let output = process(&[SHARED, PAPER], DuplicatePrecedence::First).unwrap();
+    assert!(output.bibliography_markdown.contains("Independence of the Continuum"));
+    assert_eq!(
+        output.merge_conflicts,
+        [MergeConflict::DuplicateKey {
+            key: "Cohen63".to_string(),
+            kept: "refs.bib".to_string(),
+            dropped: "refs.bib".to_string(),
+        }]
+    );
+    // The source text comes from the same file as the entry.
+    assert!(extract(DuplicatePrecedence::First).contains("@article{Cohen63,"));
+}
+
+#[test]
+fn last_bibliography_wins() {
+    let output = process(&[SHARED, PAPER], DuplicatePrecedence::Last).unwrap();
+    assert!(output.bibliography_markdown.contains("Set Theory and the Continuum"));
+    assert_eq!(output.merge_conflicts.len(), 1);
+    assert!(extract(DuplicatePrecedence::Last).contains("@book{Cohen63,"));
+}
+
+#[test]
+fn duplicate_keys_can_be_an_error() {
+    let error = process(&[SHARED, PAPER], DuplicatePrecedence::Error).err().unwrap();
+    assert_eq!(error, "duplicate key `Cohen63` in refs.bib and refs.bib");
+    // Without duplicates, nothing is reported.
+    let output = process(&[SHARED], DuplicatePrecedence::Error).unwrap();
+    assert!(output.merge_conflicts.is_empty());
+}
+
+#[test]
+fn dois_are_compared_without_resolver_or_case() {
+    let spellings = [
+        "10.1073/pnas.50.6.1143",
+        "http://dx.doi.org/10.1073/pnas.50.6.1143",
+        "doi:10.1073/PNAS.50.6.1143",
+        "DOI: 10.1073/pnas.50.6.1143",
+        "https://www.doi.org/10.1073/pnas.50.6.1143",
+    ];
+    for doi in spellings {
+        let other = format!(
+            "@misc{{Reprint,\n  title = {{Reprint}},\n  year = {{2008}},\n  doi = {{{}}}\n}}",
+            doi
+        );
+        let output = process(&[SHARED, &other], DuplicatePrecedence::First).unwrap();
+        assert_eq!(
+            output.merge_conflicts,
+            [MergeConflict::LikelyDuplicate {
+                keys: ("Cohen63".to_string(), "Reprint".to_string()),
+                reason: "same DOI 10.1073/pnas.50.6.1143".to_string(),
+            }],
+            "doi: {}",
+            doi
+        );
+    }
+}
+
+#[test]
+fn same_title_and_year_is_a_likely_duplicate() {
+    let without_doi: String = SHARED.lines().filter(|line| !line.contains("doi")).collect::<Vec<_>>().join("\n");
+    let other = without_doi.replace("Cohen63", "cohen1963");
+    let output = process(&[SHARED, &other], DuplicatePrecedence::First).unwrap();
+    assert_eq!(
+        output.merge_conflicts,
+        [MergeConflict::LikelyDuplicate {
+            keys: ("Cohen63".to_string(), "cohen1963".to_string()),
+            reason: "same title and year 1963".to_string(),
+        }]
+    );
+}
@@ -0,0 +1,98 @@
+//! Listing entries without citing them, with `<!-- nocite ... -->`
+//! directives or the `nocite` option.
+
+mod common;
+
+use common::{process, SECTIONS_BIB};
+use markdown_bib_processor::ProcessingOptions;
+
+fn nocite(keys: &[&str]) -> ProcessingOptions {
+    ProcessingOptions {
+        nocite: keys.iter().map(|key| key.to_string()).collect(),
+        ..ProcessingOptions::default()
+    }
+}
+
+/// Anchors of the entries of a bibliography, in order.
+fn anchors(bibliography: &str) -> Vec<&str> {
+    bibliography
+        .lines()
+        .filter(|line| line.starts_with("#### "))
+        .filter_map(|line| line.split(" id=\"").nth(1))
+        .filter_map(|rest| rest.split('"').next())
+        .collect()
+}
+
+#[test]
+fn explicit_keys_are_listed_without_citing() {
+    // BibTeX keys, and Markdown keys with or without the `@`, in any separator.
+    let (markdown, bibliography) =
+        process("Only [@Cohen63].", SECTIONS_BIB, &nocite(&["Kanamori08, @Godel40", "Cohen66"]));
+
+    assert_eq!(anchors(&bibliography), ["Cohen63", "Cohen66", "Godel40", "Kanamori08"]);
+    assert_eq!(markdown.matches("](#").count(), 1);
+    // Unknown keys are ignored.
+    let (_, bibliography) = process("Only [@Cohen63].", SECTIONS_BIB, &nocite(&["Nobody99"]));
+    assert_eq!(anchors(&bibliography), ["Cohen63"]);
+}
+
+#[test]
+fn cited_and_nocited_entry_is_listed_once() {
+    let options = nocite(&["Kanamori08", "@Kanamori08"]);
+    let (_, bibliography) = process("See [@Kanamori08].", SECTIONS_BIB, &options);
+    assert_eq!(anchors(&bibliography), ["Kanamori08"]);
+}
+
+#[test]
+fn star_lists_every_entry() {
+    let (_, bibliography) = process("Nothing cited.", SECTIONS_BIB, &nocite(&["*"]));
+    let mut listed = anchors(&bibliography);
+    listed.sort();
+    assert_eq!(listed, ["Cohen63", "Cohen66", "Godel40", "Heijenoort67", "Kanamori08"]);
+}
+
+#[test]
+fn keyword_filter_lists_matching_entries() {
+    let (_, bibliography) = process("Nothing cited.", SECTIONS_BIB, &nocite(&["keyword=primary"]));
+    assert_eq!(anchors(&bibliography), ["Cohen66", "Godel40"]);
+
+    let options = nocite(&["keyword=forcing type=article"]);
+    let (_, bibliography) = process("See [@Kanamori08].", SECTIONS_BIB, &options);
+    assert_eq!(anchors(&bibliography), ["Cohen63", "Kanamori08"]);
+}
+
+#[test]
+fn directive_applies_to_its_section_and_is_removed() {
+    let markdown = "# One\n\n<!-- nocite @Godel40 keyword=review -->\n[@Cohen63]\n\n# Two\n\n[@Cohen66]\n";
+    let options = ProcessingOptions { bibliography_scope_level: Some(1), ..ProcessingOptions::default() };
+    let (markdown, bibliography) = process(markdown, SECTIONS_BIB, &options);
+
+    assert!(!markdown.contains("nocite"));
+    assert_eq!(anchors(&markdown), ["Cohen63-1", "Godel40-1", "Kanamori08-1"]);
+    assert_eq!(anchors(&bibliography), ["Cohen66-2"]);
+}
+
+#[test]
+fn comments_that_only_start_with_nocite_are_kept() {
+    let (markdown, bibliography) =
+        process("<!-- nocite-me Godel40 -->\n[@Cohen63]\n", SECTIONS_BIB, &nocite(&[]));
+
+    assert!(markdown.starts_with("<!-- nocite-me Godel40 -->\n"));
+    assert_eq!(anchors(&bibliography), ["Cohen63"]);
+    let markdown = "<!-- nocite:@Godel40 -->\n[@Cohen63]\n";
+    let (_, bibliography) = process(markdown, SECTIONS_BIB, &nocite(&[]));
+    assert_eq!(anchors(&bibliography), ["Cohen63", "Godel40"]);
+}
+
+#[test]
+fn option_applies_to_the_last_section() {
+    let markdown = "# One\n\n[@Cohen63]\n\n# Two\n\n[@Cohen66]\n";
+    let options = ProcessingOptions {
+        bibliography_scope_level: Some(1),
+        ..nocite(&["Godel40"])
+    };
+    let (markdown, bibliography) = process(markdown, SECTIONS_BIB, &options);
+
+    assert_eq!(anchors(&markdown), ["Cohen63-1"]);
+    assert_eq!(anchors(&bibliography), ["Cohen66-2", "Godel40-2"]);
+}
@@ -0,0 +1,61 @@
+//! Citations rendered as footnotes by a note-class CSL style.
+
+mod common;
+
+use common::{process_with, NOTE_STYLE, SECTIONS_BIB};
+use markdown_bib_processor::ProcessingOptions;
+
+/// The processed Markdown, without the terminal formatting of the rendered
+/// citations (e.g. `\u{1b}[3m` for italics).
+fn plain_output(markdown: &str) -> String {
+    let options = ProcessingOptions::default();
+    let output = process_with(markdown, &[SECTIONS_BIB], NOTE_STYLE, "", &options).unwrap();
+    let mut plain = String::new();
+    let mut rest = output.modified_markdown.as_str();
+    while let Some(escape) = rest.find('\u{1b}') {
+        plain.push_str(&rest[..escape]);
+        rest = rest[escape..].split_once('m').map_or("", |(_, after)| after);
+    }
+    plain.push_str(rest);
+    plain
+}
+
+#[test]
+fn citations_become_numbered_footnotes() {
+    let markdown = plain_output("First [@Cohen63], then [@Godel40; @Cohen66].\n");
+
+    assert!(markdown.starts_with("First [^1], then [^2].\n"));
+    assert!(markdown.contains(
+        "[^1]: Paul J. Cohen, The Independence of the Continuum Hypothesis, 1963.\n\
+         [^2]: Kurt Gödel, The Consistency of the Continuum Hypothesis, 1940; \
+         Paul J. Cohen, Set Theory and the Continuum Hypothesis, 1966."
+    ));
+}
+
+#[test]
+fn numbering_continues_after_existing_footnotes() {
+    let markdown = plain_output("Noted.[^3] Cited [@Cohen63].\n\n[^3]: An existing note.\n");
+
+    assert!(markdown.starts_with("Noted.[^3] Cited [^4].\n"));
+    assert!(markdown.contains("[^3]: An existing note.\n\n[^4]: Paul J. Cohen,"));
+}
+
+#[test]
+fn repeated_citations_use_ibid_and_short_forms() {
+    let markdown = plain_output("[@Godel40], [@Godel40], [@Cohen63] and [@Godel40].\n");
+
+    assert!(markdown.contains(
+        "[^1]: Kurt Gödel, The Consistency of the Continuum Hypothesis, 1940.\n\
+         [^2]: Ibid.\n\
+         [^3]: Paul J. Cohen, The Independence of the Continuum Hypothesis, 1963.\n\
+         [^4]: Gödel, The Consistency of the Continuum Hypothesis."
+    ));
+}
+
+#[test]
+fn only_groups_of_resolved_keys_become_one_note() {
+    // A group with an unknown key is cited key by key, and the unknown key is left as is.
+    let markdown = plain_output("See [@Cohen63; @Nobody99] and [link](#x) [@Godel40](#x).\n");
+
+    assert!(markdown.starts_with("See [[^1]; @Nobody99] and [link](#x) [[^2]](#x).\n"));
+}
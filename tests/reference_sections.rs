@@ -0,0 +1,136 @@
+//! Reference sections, by heading level or marker, and the filters of
+//! `<!-- bibliography ... -->` markers.
+
+mod common;
+
+use common::{process, SECTIONS_BIB};
+use markdown_bib_processor::ProcessingOptions;
+
+/// Each bibliography of `text`, as its heading and the anchors of its entries.
+fn bibliographies(text: &str) -> Vec<(String, Vec<String>)> {
+    let mut lists: Vec<(String, Vec<String>)> = Vec::new();
+    for line in text.lines() {
+        if let Some(heading) = line.strip_prefix("### ") {
+            lists.push((heading.to_string(), Vec::new()));
+        } else if line.starts_with("#### ") {
+            let anchor = line.split("<a href=\"#").nth(1).and_then(|rest| rest.split('"').next());
+            lists.last_mut().unwrap().1.push(anchor.unwrap().to_string());
+        }
+    }
+    lists
+}
+
+fn list(heading: &str, anchors: &[&str]) -> (String, Vec<String>) {
+    (heading.to_string(), anchors.iter().map(|anchor| anchor.to_string()).collect())
+}
+
+#[test]
+fn scope_level_gives_each_heading_its_own_bibliography() {
+    let markdown = "# One\n\n[@Cohen63]\n\n## Detail\n\n[@Godel40]\n\n# Two\n\n[@Cohen63] and [@Kanamori08]\n";
+    let options = ProcessingOptions { bibliography_scope_level: Some(1), ..ProcessingOptions::default() };
+    let (markdown, bibliography) = process(markdown, SECTIONS_BIB, &options);
+
+    // The `##` heading is below the scope level and stays in the first section.
+    assert_eq!(bibliographies(&markdown), vec![list("References", &["Cohen63-1", "Godel40-1"])]);
+    assert_eq!(bibliographies(&bibliography), vec![list("References", &["Cohen63-2", "Kanamori08-2"])]);
+    // Citations link to the bibliography of their own section.
+    assert!(markdown.contains("[[Cohen63]](#Cohen63-1)"));
+    assert!(markdown.contains("[[Cohen63]](#Cohen63-2)"));
+    let first_bibliography = markdown.find("### References").unwrap();
+    assert!(first_bibliography < markdown.find("# Two").unwrap());
+
+    let options = ProcessingOptions { bibliography_scope_level: Some(2), ..ProcessingOptions::default() };
+    let markdown = "# One\n\n[@Cohen63]\n\n## Detail\n\n[@Godel40]\n";
+    let (markdown, _) = process(markdown, SECTIONS_BIB, &options);
+    assert_eq!(bibliographies(&markdown), vec![list("References", &["Cohen63-1"])]);
+}
+
+#[test]
+fn refsection_marker_starts_a_section() {
+    let markdown = "[@Cohen63]\n\n<!-- refsection -->\n\n[@Godel40]\n";
+    let (markdown, bibliography) = process(markdown, SECTIONS_BIB, &ProcessingOptions::default());
+
+    assert_eq!(bibliographies(&markdown), vec![list("References", &["Cohen63-1"])]);
+    // The first section's bibliography ends it, before the marker.
+    assert!(markdown.find("### References").unwrap() < markdown.find("<!-- refsection -->").unwrap());
+    assert_eq!(bibliographies(&bibliography), vec![list("References", &["Godel40-2"])]);
+}
+
+#[test]
+fn bibliography_marker_prints_the_list_in_place() {
+    let markdown = "[@Cohen63]\n\n<!-- bibliography title=\"Cited\" -->\n\nAppendix.\n";
+    let (markdown, bibliography) = process(markdown, SECTIONS_BIB, &ProcessingOptions::default());
+
+    assert!(bibliography.is_empty());
+    assert!(!markdown.contains("<!--"));
+    assert_eq!(bibliographies(&markdown), vec![list("Cited", &["Cohen63"])]);
+    assert!(markdown.find("### Cited").unwrap() < markdown.find("Appendix.").unwrap());
+}
+
+#[test]
+fn comments_that_only_start_with_the_keyword_are_not_markers() {
+    let markdown = "[@Cohen63]\n\n<!-- bibliography-block-1 -->\n<!-- bibliography-foo -->\n";
+    let (markdown, bibliography) = process(markdown, SECTIONS_BIB, &ProcessingOptions::default());
+
+    assert!(markdown.contains("<!-- bibliography-block-1 -->\n<!-- bibliography-foo -->"));
+    assert_eq!(bibliographies(&markdown), []);
+    assert_eq!(bibliographies(&bibliography), vec![list("References", &["Cohen63"])]);
+    // Without arguments, the marker still prints the list.
+    let markdown = "[@Cohen63]\n\n<!-- bibliography-->\n";
+    let (markdown, _) = process(markdown, SECTIONS_BIB, &ProcessingOptions::default());
+    assert_eq!(bibliographies(&markdown), vec![list("References", &["Cohen63"])]);
+}
+
+#[test]
+fn type_filters_split_a_bibliography() {
+    let markdown = "[@Cohen63], [@Cohen66] and [@Godel40].\n\n\
+        <!-- bibliography type=book title=\"Books\" -->\n\
+        <!-- bibliography nottype=book title=\"Articles\" -->\n";
+    let (markdown, _) = process(markdown, SECTIONS_BIB, &ProcessingOptions::default());
+
+    assert_eq!(
+        bibliographies(&markdown),
+        vec![list("Books", &["Cohen66", "Godel40"]), list("Articles", &["Cohen63"])]
+    );
+}
+
+#[test]
+fn keyword_filters_select_by_keyword() {
+    let markdown = "[@Cohen63], [@Cohen66], [@Godel40] and [@Kanamori08].\n\n\
+        <!-- bibliography keyword=forcing title=\"Forcing\" -->\n\
+        <!-- bibliography keyword=primary notkeyword=forcing title=\"Other primary\" -->\n";
+    let (markdown, _) = process(markdown, SECTIONS_BIB, &ProcessingOptions::default());
+
+    assert_eq!(
+        bibliographies(&markdown),
+        vec![list("Forcing", &["Cohen63", "Cohen66"]), list("Other primary", &["Godel40"])]
+    );
+}
+
+#[test]
+fn field_filters_match_values_or_presence() {
+    let markdown = "[@Cohen63], [@Godel40] and [@Kanamori08].\n\n\
+        <!-- bibliography field.journal=\"bulletin of symbolic logic\" title=\"Bulletin\" -->\n\
+        <!-- bibliography field.publisher=* title=\"Published\" -->\n";
+    let (markdown, _) = process(markdown, SECTIONS_BIB, &ProcessingOptions::default());
+
+    // Values are compared without regard to case.
+    assert_eq!(
+        bibliographies(&markdown),
+        vec![list("Bulletin", &["Kanamori08"]), list("Published", &["Godel40"])]
+    );
+}
+
+#[test]
+fn entry_in_several_filtered_lists_is_one_link_target() {
+    let markdown = "[@Cohen66] and [@Godel40].\n\n\
+        <!-- bibliography type=book title=\"Books\" -->\n\
+        <!-- bibliography keyword=forcing title=\"Forcing\" -->\n";
+    let (markdown, _) = process(markdown, SECTIONS_BIB, &ProcessingOptions::default());
+
+    assert_eq!(
+        bibliographies(&markdown),
+        vec![list("Books", &["Cohen66", "Godel40"]), list("Forcing", &["Cohen66"])]
+    );
+    assert_eq!(markdown.matches("id=\"Cohen66\"").count(), 1);
+}
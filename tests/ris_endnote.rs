@@ -0,0 +1,94 @@
+//! RIS and EndNote XML records, translated to entries through CSL items.
+
+use hayagriva::Entry;
+use markdown_bib_processor::{import_bibliography, BibliographyFormat, UnmappedField};
+
+const RIS: &str = include_str!("fixtures/records.ris");
+const ENDNOTE_XML: &str = include_str!("fixtures/records.xml");
+
+fn unmapped(fields: &[UnmappedField]) -> Vec<(&str, &str, &str)> {
+    fields
+        .iter()
+        .map(|field| (field.key.as_str(), field.field.as_str(), field.value.as_str()))
+        .collect()
+}
+
+fn assert_article(article: &Entry) {
+    assert_eq!(article.title().unwrap().to_string(), "The Independence of the Continuum Hypothesis");
+    let author = &article.authors().unwrap()[0];
+    assert_eq!((author.name.as_str(), author.given_name.as_deref()), ("Cohen", Some("Paul J.")));
+    assert_eq!(article.date().unwrap().year, 1963);
+    assert_eq!(article.doi(), Some("10.1073/pnas.50.6.1143"));
+    assert_eq!(article.page_range().map(ToString::to_string).as_deref(), Some("1143-1148"));
+    let journal = &article.parents()[0];
+    assert_eq!(journal.title().unwrap().to_string(), "Proceedings of the National Academy of Sciences");
+    assert_eq!(journal.volume().map(ToString::to_string).as_deref(), Some("50"));
+    assert_eq!(journal.issue().map(ToString::to_string).as_deref(), Some("6"));
+}
+
+fn assert_chapter(chapter: &Entry) {
+    assert_eq!(chapter.title().unwrap().to_string(), "Computing Machinery and Intelligence");
+    assert_eq!(chapter.authors().unwrap()[0].name, "Turing");
+    assert_eq!(chapter.date().unwrap().year, 2004);
+    assert_eq!(chapter.page_range().map(ToString::to_string).as_deref(), Some("433-464"));
+    let book = &chapter.parents()[0];
+    assert_eq!(book.title().unwrap().to_string(), "The Essential Turing");
+    let publisher = book.publisher().unwrap();
+    assert_eq!(publisher.name().map(ToString::to_string).as_deref(), Some("Clarendon Press"));
+    assert_eq!(publisher.location().map(ToString::to_string).as_deref(), Some("Oxford"));
+    let editors = chapter.editors().or_else(|| book.editors()).unwrap();
+    assert_eq!(editors[0].name, "Copeland");
+}
+
+#[test]
+fn ris_article_and_chapter() {
+    let (library, unmapped_fields) = import_bibliography(RIS, None).unwrap();
+    assert_eq!(library.len(), 2);
+
+    let article = library.get("Cohen63").unwrap();
+    assert_article(article);
+    assert_eq!(article.date().unwrap().month, Some(11));
+    let journal = &article.parents()[0];
+    let short_title = journal.title().unwrap().short.as_ref().map(ToString::to_string);
+    assert_eq!(short_title.as_deref(), Some("Proc. Natl. Acad. Sci."));
+
+    // Records without an `ID` are keyed by author and year.
+    let chapter = library.get("turing2004").unwrap();
+    assert_chapter(chapter);
+    assert_eq!(
+        chapter.note().map(ToString::to_string).as_deref(),
+        Some("First published in Mind, 1950, with a note wrapped onto a second line.")
+    );
+
+    assert_eq!(
+        unmapped(&unmapped_fields),
+        [
+            ("Cohen63", "M1", "6"),
+            ("Cohen63", "C1", "Stanford University"),
+            ("turing2004", "RN", "Reprinted"),
+        ]
+    );
+}
+
+#[test]
+fn endnote_xml_article_and_chapter() {
+    let (library, unmapped_fields) = import_bibliography(ENDNOTE_XML, None).unwrap();
+    assert_eq!(library.len(), 2);
+
+    assert_article(library.get("cohen1963").unwrap());
+    assert_chapter(library.get("turing2004").unwrap());
+
+    assert_eq!(
+        unmapped(&unmapped_fields),
+        [
+            ("cohen1963", "custom1", "Stanford University"),
+            ("turing2004", "research-notes", "Reprinted"),
+        ]
+    );
+}
+
+#[test]
+fn formats_are_detected_from_the_content() {
+    assert_eq!(BibliographyFormat::detect(RIS), BibliographyFormat::Ris);
+    assert_eq!(BibliographyFormat::detect(ENDNOTE_XML), BibliographyFormat::EndNoteXml);
+}